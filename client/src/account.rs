@@ -0,0 +1,42 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use tui_chat_app_common::account::AccountToken;
+
+//
+
+/// `<config dir>/tui-chat-app/account`
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("tui-chat-app").join("account"))
+}
+
+/// Read the account token, a new
+/// account is created on the first run
+pub fn load_or_create(path: &Path) -> io::Result<AccountToken> {
+    match fs::read_to_string(path) {
+        Ok(file) => file
+            .parse()
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let token = AccountToken::generate();
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = new_secret_file(path)?;
+            writeln!(file, "{token}")?;
+            Ok(token)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Only readable by the owner
+fn new_secret_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
@@ -0,0 +1,204 @@
+use std::time::Duration;
+use tui_chat_app_common::{
+    client::ModerationAction, command::split_word, duration::parse_duration,
+};
+
+//
+
+/// Chat input starting with a `/`
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// `member` is a member id or the start of one
+    Moderate {
+        member: String,
+        action: ModerationAction,
+    },
+
+    /// Show the latest moderation actions
+    AuditLog,
+
+    /// Show, collapse or hide the join and leave lines
+    JoinLines,
+
+    /// `None` turns slow mode off
+    SlowMode(Option<Duration>),
+}
+
+//
+
+/// Commands that act on a member
+const COMMANDS: [&str; 5] = ["kick", "mute", "unmute", "ban", "role"];
+
+pub const HELP: &str = "/kick <member> [reason], /mute <member> <duration>, \
+    /unmute <member>, /ban <member> [duration] [reason], /role <member> <role>, /audit, /joins, \
+    /slowmode <duration|off>";
+
+//
+
+/// `None` if the input isn't a command
+pub fn parse(input: &str) -> Option<Result<Command, String>> {
+    let input = input.trim().strip_prefix('/')?;
+    let (name, args) = split_word(input);
+    Some(parse_command(name, args))
+}
+
+fn parse_command(name: &str, args: &str) -> Result<Command, String> {
+    match name {
+        "audit" => return Ok(Command::AuditLog),
+        "joins" => return Ok(Command::JoinLines),
+        "slowmode" => {
+            return match split_word(args).0 {
+                "" => Err(format!("/slowmode needs a duration or off, try {HELP}")),
+                "off" => Ok(Command::SlowMode(None)),
+                duration => Ok(Command::SlowMode(Some(parse_duration(duration)?))),
+            }
+        }
+        _ => {}
+    }
+
+    let (member, rest) = split_word(args);
+    if member.is_empty() && COMMANDS.contains(&name) {
+        return Err(format!("/{name} needs a member, try {HELP}"));
+    }
+
+    let action = match name {
+        "kick" => ModerationAction::Kick {
+            reason: rest.to_string(),
+        },
+        "mute" => ModerationAction::Mute {
+            duration: Some(parse_duration(split_word(rest).0)?),
+        },
+        "unmute" => ModerationAction::Mute { duration: None },
+        "ban" => {
+            // the duration is optional
+            let (first, reason) = split_word(rest);
+            match parse_duration(first) {
+                Ok(duration) => ModerationAction::Ban {
+                    reason: reason.to_string(),
+                    duration: Some(duration),
+                },
+                Err(_) => ModerationAction::Ban {
+                    reason: rest.to_string(),
+                    duration: None,
+                },
+            }
+        }
+        "role" => ModerationAction::SetRole {
+            role: split_word(rest).0.parse()?,
+        },
+        _ => return Err(format!("Unknown command /{name}, try {HELP}")),
+    };
+
+    Ok(Command::Moderate {
+        member: member.to_string(),
+        action,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tui_chat_app_common::role::Role;
+
+    fn moderate(input: &str) -> (String, ModerationAction) {
+        match parse(input) {
+            Some(Ok(Command::Moderate { member, action })) => (member, action),
+            other => panic!("`{input}` parsed to {other:?}"),
+        }
+    }
+
+    fn error(input: &str) -> String {
+        match parse(input) {
+            Some(Err(err)) => err,
+            other => panic!("`{input}` parsed to {other:?}"),
+        }
+    }
+
+    #[test]
+    fn not_a_command() {
+        assert!(parse("hello /kick").is_none());
+        assert!(parse("").is_none());
+    }
+
+    #[test]
+    fn kick_with_and_without_reason() {
+        let (member, action) = moderate("  /kick  3f2a   being  rude ");
+        assert_eq!(member, "3f2a");
+        assert!(matches!(action, ModerationAction::Kick { reason } if reason == "being  rude"));
+
+        let (_, action) = moderate("/kick 3f2a");
+        assert!(matches!(action, ModerationAction::Kick { reason } if reason.is_empty()));
+    }
+
+    #[test]
+    fn mute_and_unmute() {
+        let (_, action) = moderate("/mute 3f2a 5m");
+        assert!(matches!(
+            action,
+            ModerationAction::Mute { duration: Some(duration) } if duration == Duration::from_secs(300)
+        ));
+
+        let (_, action) = moderate("/unmute 3f2a");
+        assert!(matches!(action, ModerationAction::Mute { duration: None }));
+    }
+
+    #[test]
+    fn ban_duration_is_optional() {
+        let (_, action) = moderate("/ban 3f2a 7d raid");
+        assert!(matches!(
+            action,
+            ModerationAction::Ban { reason, duration: Some(duration) }
+                if reason == "raid" && duration == Duration::from_secs(7 * 24 * 60 * 60)
+        ));
+
+        let (_, action) = moderate("/ban 3f2a raid again");
+        assert!(matches!(
+            action,
+            ModerationAction::Ban { reason, duration: None } if reason == "raid again"
+        ));
+
+        let (_, action) = moderate("/ban 3f2a");
+        assert!(matches!(
+            action,
+            ModerationAction::Ban { reason, duration: None } if reason.is_empty()
+        ));
+    }
+
+    #[test]
+    fn role() {
+        let (_, action) = moderate("/role 3f2a moderator");
+        assert!(matches!(
+            action,
+            ModerationAction::SetRole {
+                role: Role::Moderator
+            }
+        ));
+    }
+
+    #[test]
+    fn commands_without_a_member() {
+        assert!(matches!(parse("/audit"), Some(Ok(Command::AuditLog))));
+        assert!(matches!(parse("/joins"), Some(Ok(Command::JoinLines))));
+        assert!(matches!(
+            parse("/slowmode 30s"),
+            Some(Ok(Command::SlowMode(Some(duration)))) if duration == Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            parse("/slowmode off"),
+            Some(Ok(Command::SlowMode(None)))
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(error("/kick").contains("needs a member"));
+        assert!(error("/ban   ").contains("needs a member"));
+        assert!(error("/mute 3f2a").contains("Invalid duration"));
+        assert!(error("/mute 3f2a 5 minutes").contains("Invalid duration"));
+        assert!(error("/mute 3f2a 0s").contains("Invalid duration"));
+        assert!(error("/role 3f2a king").contains("not a role"));
+        assert!(error("/slowmode").contains("needs a duration"));
+        assert!(error("/slowmode soon").contains("Invalid duration"));
+        assert!(error("/dance").contains("Unknown command"));
+    }
+}
@@ -6,16 +6,27 @@ use tokio::{
     time::Instant,
 };
 use tui_chat_app_common::{
+    account::LoginToken,
     client::{ClientChatPacket, ClientInitPacket, ClientPacket},
-    compat::COMPAT_INFO,
-    server::{ServerInitPacket, ServerPacket},
+    compat::{VersionMismatchPolicy, COMPAT_INFO},
+    server::{ServerChatPacket, ServerInitPacket, ServerPacket},
+    trace::{Direction, PacketTrace},
     FromPacketBytes, IntoPacketBytes,
 };
 
 //
 
-pub async fn handler(socket: Socket, recv: Receiver<ClientPacket>, send: Sender<ServerPacket>) {
-    if handler_try(socket, recv, send).await.is_none() {
+pub async fn handler(
+    socket: Socket,
+    recv: Receiver<ClientPacket>,
+    send: Sender<ServerPacket>,
+    trace: PacketTrace,
+    token: LoginToken,
+) {
+    if handler_try(socket, recv, send, &trace, token)
+        .await
+        .is_none()
+    {
         eprintln!("Closed");
     }
 }
@@ -24,14 +35,65 @@ async fn handler_try(
     mut socket: Socket,
     mut recv: Receiver<ClientPacket>,
     send: Sender<ServerPacket>,
+    trace: &PacketTrace,
+    token: LoginToken,
 ) -> Option<()> {
-    // Init state
+    init_state(&mut socket, trace, token, false).await?;
+
+    let mut hb = Instant::now() + Duration::SECOND;
 
-    let init = ClientInitPacket::ClientInfo(COMPAT_INFO).into_bytes();
-    socket.send(Packet::ordered(init, None)).await?;
-    match recv_packet(&mut socket).await? {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(hb) => {
+                send_packet(&socket, trace, ClientChatPacket::KeepAlive).await?;
+                hb = Instant::now() + Duration::SECOND;
+            }
+            Some(to_send) = recv.recv() => {
+                send_packet(&socket, trace, to_send).await?;
+            }
+            Some(to_send) = recv_packet(&mut socket, trace) => {
+                let resync = matches!(to_send, ServerPacket::Chat(ServerChatPacket::InvalidState { .. }));
+                let disconnect = matches!(to_send, ServerPacket::Chat(ServerChatPacket::Disconnect { .. }));
+                send.send(to_send).await.ok()?;
+
+                if resync {
+                    // the server went back to the init state,
+                    // the tui requests everything again
+                    init_state(&mut socket, trace, token, true).await?;
+                }
+                if disconnect {
+                    return Some(());
+                }
+            }
+        }
+    }
+}
+
+/// `resync` skips chat packets that were
+/// sent before the server went back to
+/// the init state
+async fn init_state(
+    socket: &mut Socket,
+    trace: &PacketTrace,
+    token: LoginToken,
+    resync: bool,
+) -> Option<()> {
+    let login = ClientInitPacket::Login {
+        info: COMPAT_INFO,
+        token,
+    };
+    send_packet(socket, trace, login).await?;
+
+    let packet = loop {
+        match recv_packet(socket, trace).await? {
+            ServerPacket::Chat(_) if resync => continue,
+            packet => break packet,
+        }
+    };
+
+    match packet {
         ServerPacket::Init(ServerInitPacket::Success(i)) => {
-            if let Err(err) = i.compatible(COMPAT_INFO) {
+            if let Err(err) = i.compatible(COMPAT_INFO, VersionMismatchPolicy::default()) {
                 eprintln!("{err}");
                 return None;
             }
@@ -46,24 +108,23 @@ async fn handler_try(
         }
     };
 
-    let mut hb = Instant::now() + Duration::SECOND;
+    Some(())
+}
 
-    loop {
-        tokio::select! {
-            _ = tokio::time::sleep_until(hb) => {
-                socket.send(Packet::ordered(ClientChatPacket::KeepAlive.into_bytes(), None)).await?;
-                hb = Instant::now() + Duration::SECOND;
-            }
-            Some(to_send) = recv.recv() => {
-                socket.send(Packet::ordered(to_send.into_bytes(), None)).await?;
-            }
-            Some(to_send) = recv_packet(&mut socket) => {
-                send.send(to_send).await.ok()?;
-            }
-        }
-    }
+async fn recv_packet(socket: &mut Socket, trace: &PacketTrace) -> Option<ServerPacket> {
+    let packet = ServerPacket::from_bytes(socket.recv().await?.bytes)?;
+    trace.log(Direction::Recv, socket.remote(), &packet);
+    Some(packet)
 }
 
-async fn recv_packet(socket: &mut Socket) -> Option<ServerPacket> {
-    ServerPacket::from_bytes(socket.recv().await?.bytes)
+async fn send_packet(
+    socket: &Socket,
+    trace: &PacketTrace,
+    packet: impl Into<ClientPacket>,
+) -> Option<()> {
+    let packet = packet.into();
+    trace.log(Direction::Send, socket.remote(), &packet);
+    socket
+        .send(Packet::ordered(packet.into_bytes(), None))
+        .await
 }
@@ -4,17 +4,27 @@
 
 use clap::Parser;
 use eznet::socket::Socket;
+use read_marker::ReadMarker;
 use std::{
-    net::{Ipv6Addr, SocketAddrV6},
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    path::PathBuf,
     time::Duration,
 };
 use tokio::sync::mpsc::channel;
+use tui::JoinLines;
+use tui_chat_app_common::trace::PacketTrace;
 
 //
 
+pub mod account;
+pub mod command;
 pub mod handler;
+pub mod read_marker;
 pub mod tui;
 
+#[cfg(test)]
+mod test_util;
+
 //
 
 #[derive(Parser)]
@@ -27,6 +37,38 @@ pub struct CliArgs {
     /// Disable TUI unicode symbols
     #[clap(short = 'u', long)]
     no_unicode: bool,
+
+    /// How join and leave lines are shown, `/joins` changes it while chatting
+    #[clap(long, value_enum, default_value = "collapse")]
+    join_lines: JoinLines,
+
+    /// Log every sent and received packet to this file
+    #[clap(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Account token file, created on the first run
+    ///
+    /// Keep it secret, anyone with it can use the account
+    ///
+    /// [default: `<config dir>/tui-chat-app/account`]
+    #[clap(long, value_name = "FILE")]
+    account: Option<PathBuf>,
+
+    /// Last read message file, one line per server
+    ///
+    /// [default: `<config dir>/tui-chat-app/read-markers`]
+    #[clap(long, value_name = "FILE")]
+    read_markers: Option<PathBuf>,
+
+    /// Keep the last read message local instead of syncing it to the account
+    #[clap(long)]
+    no_read_sync: bool,
+
+    /// Print the account id on the server and exit
+    ///
+    /// Server admins use it to give the account permissions
+    #[clap(long)]
+    account_id: bool,
 }
 
 #[tokio::main]
@@ -34,17 +76,65 @@ async fn main() {
     let CliArgs {
         tui_tick_rate,
         no_unicode,
+        join_lines,
+        trace,
+        account,
+        read_markers,
+        no_read_sync,
+        account_id,
     } = CliArgs::parse();
 
+    let Some(account) = account.or_else(account::default_path) else {
+        eprintln!("No config directory, use --account <FILE>");
+        return;
+    };
+    let token = match account::load_or_create(&account) {
+        Ok(token) => token,
+        Err(err) => {
+            eprintln!("Failed to load the account {}: {err}", account.display());
+            return;
+        }
+    };
+
+    let addr: SocketAddr = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 13331, 0, 0).into();
+
+    // every server gets its own login token
+    let token = token.login_token(&addr.to_string());
+    if account_id {
+        println!("{}", token.account_id());
+        return;
+    }
+
+    let trace = match trace {
+        Some(path) => match PacketTrace::open(&path) {
+            Ok(trace) => trace,
+            Err(err) => {
+                eprintln!("Failed to open packet trace file {}: {err}", path.display());
+                return;
+            }
+        },
+        None => PacketTrace::default(),
+    };
+
+    let read_markers = read_markers.or_else(read_marker::default_path);
+    let read_marker = match ReadMarker::load(read_markers.clone(), addr.to_string(), !no_read_sync)
+    {
+        Ok(read_marker) => read_marker,
+        Err(err) => {
+            let path = read_markers.unwrap_or_default();
+            eprintln!("Failed to load read markers {}: {err}", path.display());
+            return;
+        }
+    };
+
     let (t_send, recv) = channel(256);
     let (send, t_recv) = channel(256);
 
     tokio::spawn(async move {
-        let addr = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 13331, 0, 0).into();
         let socket = Socket::connect(addr).await;
 
         match socket {
-            Ok(socket) => handler::handler(socket, t_recv, t_send).await,
+            Ok(socket) => handler::handler(socket, t_recv, t_send, trace, token).await,
             Err(err) => eprintln!("{err}"),
         }
     });
@@ -52,6 +142,8 @@ async fn main() {
     tui::run(
         Duration::from_millis(tui_tick_rate as _),
         no_unicode,
+        join_lines,
+        read_marker,
         recv,
         send,
     )
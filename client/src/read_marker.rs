@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use uuid::Uuid;
+
+//
+
+/// The newest read message on a server
+///
+/// Every server has a `<server> <message id>` line in the same file
+pub struct ReadMarker {
+    /// `None` keeps the marker in memory only
+    path: Option<PathBuf>,
+    server: String,
+
+    /// Also keep the marker on the server,
+    /// so that it follows the account to other clients
+    pub sync: bool,
+
+    pub message_id: Option<Uuid>,
+}
+
+//
+
+/// `<config dir>/tui-chat-app/read-markers`
+pub fn default_path() -> Option<PathBuf> {
+    Some(
+        dirs::config_dir()?
+            .join("tui-chat-app")
+            .join("read-markers"),
+    )
+}
+
+impl ReadMarker {
+    /// No marker if nothing was saved for `server` yet
+    pub fn load(path: Option<PathBuf>, server: String, sync: bool) -> io::Result<Self> {
+        let message_id = match &path {
+            Some(path) => read_lines(path)?
+                .iter()
+                .find_map(|(s, message_id)| (*s == server).then_some(*message_id)),
+            None => None,
+        };
+
+        Ok(Self {
+            path,
+            server,
+            sync,
+            message_id,
+        })
+    }
+
+    /// Write the marker, keeping the lines of the other servers
+    pub fn save(&self) -> io::Result<()> {
+        let (Some(path), Some(message_id)) = (&self.path, self.message_id) else {
+            return Ok(());
+        };
+
+        let mut lines = read_lines(path)?;
+        lines.retain(|(server, _)| *server != self.server);
+        lines.push((self.server.clone(), message_id));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file: String = lines
+            .into_iter()
+            .map(|(server, message_id)| format!("{server} {message_id}\n"))
+            .collect();
+
+        // a crash mid write would lose the markers of every server,
+        // unique so that two clients saving at once can't tear each other
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}.{n}.tmp", process::id()));
+        if let Err(err) = fs::write(&tmp, file).and_then(|_| fs::rename(&tmp, path)) {
+            let _ = fs::remove_file(&tmp);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// Empty if nothing was saved yet, invalid lines are skipped
+fn read_lines(path: &PathBuf) -> io::Result<Vec<(String, Uuid)>> {
+    let file = match fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    Ok(parse_lines(&file))
+}
+
+fn parse_lines(file: &str) -> Vec<(String, Uuid)> {
+    file.lines()
+        .filter_map(|line| {
+            let (server, message_id) = line.trim().rsplit_once(' ')?;
+            Some((server.to_string(), message_id.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn parse_skips_invalid_lines() {
+        let id = Uuid::new_v4();
+        let file = format!(
+            "[::1]:13331 {id}\n\
+            \n\
+            no-id\n\
+            example.com:13331 not-a-uuid\n  \
+            example.com:13331 {id}  \n"
+        );
+
+        assert_eq!(
+            parse_lines(&file),
+            [
+                ("[::1]:13331".to_string(), id),
+                ("example.com:13331".to_string(), id),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_keeps_other_servers() {
+        let dir = TempDir::new();
+        let path = dir.join("read-markers");
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut first = ReadMarker::load(Some(path.clone()), "first:1".into(), false).unwrap();
+        assert_eq!(first.message_id, None);
+        first.message_id = Some(a);
+        first.save().unwrap();
+
+        let mut second = ReadMarker::load(Some(path.clone()), "second:1".into(), false).unwrap();
+        second.message_id = Some(b);
+        second.save().unwrap();
+        second.message_id = Some(a);
+        second.save().unwrap();
+
+        let first = ReadMarker::load(Some(path.clone()), "first:1".into(), false).unwrap();
+        let second = ReadMarker::load(Some(path.clone()), "second:1".into(), false).unwrap();
+        assert_eq!(first.message_id, Some(a));
+        assert_eq!(second.message_id, Some(a));
+        assert_eq!(read_lines(&path).unwrap().len(), 2);
+        assert_eq!(
+            fs::read_dir(&*dir).unwrap().count(),
+            1,
+            "a temporary file was left behind"
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use crate::{
+    command::{self, Command},
+    read_marker::ReadMarker,
+};
 use ::tui::{Frame, Terminal};
 use chrono::{DateTime, Local};
+use clap::ValueEnum;
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
@@ -9,11 +14,10 @@ use crossterm::{
 };
 use notify_rust::Notification;
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     error::Error,
-    fmt::{self, Display, Formatter},
     io::{self, Stdout},
-    mem::swap,
+    mem::{self, swap},
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc::{Receiver, Sender};
@@ -26,7 +30,8 @@ use tui::{
 };
 use tui_chat_app_common::{
     client::{ClientChatPacket, ClientPacket},
-    server::{ServerChatPacket, ServerPacket},
+    role::Role,
+    server::{AuditEntry, HistoryMessage, NoticeKind, ServerChatPacket, ServerPacket},
 };
 use uuid::Uuid;
 
@@ -35,6 +40,8 @@ use uuid::Uuid;
 pub async fn run(
     tick_rate: Duration,
     no_unicode: bool,
+    join_lines: JoinLines,
+    read_marker: ReadMarker,
     recv: Receiver<ServerPacket>,
     send: Sender<ClientPacket>,
 ) -> Result<(), Box<dyn Error>> {
@@ -45,7 +52,7 @@ pub async fn run(
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let result = App::new(no_unicode, recv, send)
+    let result = App::new(no_unicode, join_lines, read_marker, recv, send)
         .run(&mut terminal, tick_rate)
         .await;
 
@@ -65,20 +72,77 @@ pub async fn run(
 
 struct App {
     no_unicode: bool,
+    join_lines: JoinLines,
     should_close: bool,
 
     // input or messages
     focus: Focus,
     input: String,
+    /// The input replaces this message instead of sending a new one
+    editing: Option<Uuid>,
 
     messages: Vec<Message>,
     all_messages: HashMap<Uuid, HashMap<Uuid, String>>,
+    members: BTreeSet<Uuid>,
+    roles: HashMap<Uuid, Role>,
     self_id: SelfUuid,
+    /// Members that joined before the server said which one is
+    /// the user, their join lines wait so that the own one is skipped
+    early_joins: Vec<Uuid>,
+    server_info: Option<ServerInfo>,
+
+    /// Why the server last asked for a resync
+    resync: Option<(Instant, String)>,
+
+    /// Why the server closed the connection
+    disconnected: Option<String>,
+
+    /// Latest flood warning from the server
+    warning: Option<(Instant, String)>,
+
+    /// Sent commands waiting for an answer
+    commands: HashMap<Uuid, Command>,
+
+    /// Sent edits and removals waiting for an answer, by message id
+    ///
+    /// Answered with the id of the message, but a
+    /// rejection doesn't mean that the message failed
+    changes: HashMap<Uuid, MessageChange>,
+
+    /// Shown instead of the messages until a key is pressed
+    audit_log: Option<Vec<AuditEntry>>,
+
+    /// Members typing and when they were last seen typing
+    typing: HashMap<Uuid, Instant>,
+    /// When the server was last told that the user is typing
+    typing_sent: Option<Instant>,
+
+    /// The newest message the user has seen
+    read_marker: ReadMarker,
+    /// The new messages divider is drawn above this message,
+    /// the last one read before the newer messages were marked read
+    divider: Option<Uuid>,
+    /// The marker was set to the newest message on the first visit,
+    /// one synced from the account replaces it even if it is older
+    read_marker_guessed: bool,
 
     recv: Receiver<ServerPacket>,
     send: Sender<ClientPacket>,
 }
 
+/// How join and leave lines are shown in the chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum JoinLines {
+    /// Every join and leave on its own line
+    Show,
+
+    /// Joins and leaves in a row are counted on one line
+    Collapse,
+
+    Hide,
+}
+
+/// `Chat::idx` is counted from the newest message
 #[derive(Debug, Clone, Copy)]
 enum Focus {
     Input { idx: usize },
@@ -92,36 +156,109 @@ enum SelfUuid {
     None,
 }
 
+struct ServerInfo {
+    name: String,
+    description: String,
+    motd: String,
+}
+
 struct Message {
     sender_id: Uuid,
     message_id: Uuid,
     timestamp: DateTime<Local>,
+    state: MessageState,
 }
 
-//
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MessageState {
+    /// Message from someone else
+    Received,
 
-impl Display for SelfUuid {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            SelfUuid::Some(uuid) => write!(f, "{uuid}"),
-            SelfUuid::Pending(_) => write!(f, "name pending"),
-            SelfUuid::None => write!(f, "name none"),
-        }
-    }
+    /// Own message waiting for the server to ack it
+    Pending(Instant),
+
+    /// Own message acked by the server
+    Sent,
+
+    /// Own message rejected by the server or timed out
+    Failed(String),
+
+    /// Line from the server or the client itself, not from a member
+    ///
+    /// `None` is feedback from the client, like a failed command
+    Notice(Option<NoticeKind>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MessageChange {
+    Edit,
+    Remove,
 }
 
+//
+
+/// Own messages that the server hasn't acked
+/// in this time are marked as failed
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the resync reason stays in the title
+const RESYNC_NOTICE: Duration = Duration::from_secs(10);
+
+/// How long a server warning stays in the title
+const WARNING_NOTICE: Duration = Duration::from_secs(10);
+
+/// Longer MOTDs are cut
+const MAX_BANNER_LINES: u16 = 3;
+
+/// The server is told at most this often that the user is typing
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Members stop typing if nothing is heard from them in this time
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// More members typing at once are only counted
+const MAX_TYPING_NAMES: usize = 3;
+
+//
+
 impl App {
-    fn new(no_unicode: bool, recv: Receiver<ServerPacket>, send: Sender<ClientPacket>) -> Self {
+    fn new(
+        no_unicode: bool,
+        join_lines: JoinLines,
+        read_marker: ReadMarker,
+        recv: Receiver<ServerPacket>,
+        send: Sender<ClientPacket>,
+    ) -> Self {
         Self {
             no_unicode,
+            join_lines,
             should_close: false,
 
             focus: Focus::Input { idx: 0 },
             input: String::new(),
+            editing: None,
 
             messages: vec![],
             all_messages: HashMap::new(),
+            members: BTreeSet::new(),
+            roles: HashMap::new(),
             self_id: SelfUuid::None,
+            early_joins: vec![],
+            server_info: None,
+
+            resync: None,
+            disconnected: None,
+            warning: None,
+            commands: HashMap::new(),
+            changes: HashMap::new(),
+            audit_log: None,
+
+            typing: HashMap::new(),
+            typing_sent: None,
+
+            read_marker,
+            divider: None,
+            read_marker_guessed: false,
 
             recv,
             send,
@@ -142,6 +279,9 @@ impl App {
                 .unwrap_or_else(|| Duration::from_secs(0));
             if crossterm::event::poll(timeout)? {
                 if let Event::Key(key) = event::read()? {
+                    // a key press means the user has seen the chat
+                    self.mark_read().await;
+
                     match key.code {
                         /* KeyCode::Char(c) => app.on_key(c),
                         KeyCode::Left => app.on_left(),
@@ -152,6 +292,7 @@ impl App {
                             self.should_close = true
                         }
                         KeyCode::Esc => self.should_close = true,
+                        KeyCode::Tab => self.toggle_focus(),
                         _ => self.key_event(key).await,
                     }
                 }
@@ -212,7 +353,30 @@ impl App {
 
         // server list
         let server_list_view = split[0];
-        frame.render_widget(Block::default().title("Servers"), server_list_view);
+        let unread = self.unread();
+        let servers: Vec<Spans> = self
+            .server_info
+            .iter()
+            .flat_map(|info| {
+                let name = Span::styled(
+                    info.name.as_str(),
+                    Style::default()
+                        .fg(Color::White)
+                        .add_modifier(Modifier::BOLD),
+                );
+                let unread = (unread != 0).then(|| {
+                    Span::styled(
+                        format!("{unread} unread"),
+                        Style::default().fg(Color::LightRed),
+                    )
+                });
+                [Some(name), unread].into_iter().flatten().map(Spans::from)
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(servers).block(Block::default().title("Servers")),
+            server_list_view,
+        );
 
         // server
         let server_view = split[2];
@@ -236,44 +400,194 @@ impl App {
 
         // member list
         let member_view = split[2];
-        frame.render_widget(Block::default().title("Online - 0"), member_view);
+        // the most trusted members first
+        let mut sorted: Vec<(Role, Uuid)> = self
+            .members
+            .iter()
+            .map(|member_id| (self.role(*member_id), *member_id))
+            .collect();
+        sorted.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let members: Vec<Spans> = sorted
+            .into_iter()
+            .map(|(role, member_id)| {
+                let mut name = member_id.to_string();
+                if self.self_id == SelfUuid::Some(member_id) {
+                    name.insert_str(0, "(you) ");
+                }
+                let badge = match role {
+                    Role::Member => None,
+                    role => Some(Span::styled(
+                        format!("[{role}] "),
+                        Style::default().fg(role_color(role)),
+                    )),
+                };
+                badge
+                    .into_iter()
+                    .chain([Span::styled(name, Style::default().fg(Color::LightCyan))])
+                    .collect::<Vec<_>>()
+                    .into()
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(members)
+                .block(Block::default().title(format!("Online - {}", self.members.len()))),
+            member_view,
+        );
     }
 
     fn draw_chat(&mut self, frame: &mut Frame<CrosstermBackend<Stdout>>, rect: Rect) {
+        let motd: Vec<&str> = self
+            .server_info
+            .iter()
+            .flat_map(|info| info.motd.lines())
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        // the banner only takes space the messages don't need
+        let banner_height = (motd.len() as u16)
+            .min(MAX_BANNER_LINES)
+            .min(rect.height.saturating_sub(14));
+
         let split = Layout::default()
             .constraints([
                 Constraint::Length(1),
                 Constraint::Length(1),
+                Constraint::Length(banner_height),
                 Constraint::Min(10),
                 Constraint::Length(1),
                 Constraint::Length(1),
+                Constraint::Length(1),
             ])
             .direction(Direction::Vertical)
             .split(rect);
         frame.render_widget(Block::default().borders(Borders::BOTTOM), split[1]);
-        frame.render_widget(Block::default().borders(Borders::BOTTOM), split[3]);
+        frame.render_widget(Block::default().borders(Borders::BOTTOM), split[5]);
 
         // title
         let title_view = split[0];
-        frame.render_widget(
-            Block::default().title(format!("Server name - {}", self.self_id)),
-            title_view,
-        );
+        let mut title = match &self.server_info {
+            Some(info) if info.description.is_empty() => info.name.clone(),
+            Some(info) => format!("{} - {}", info.name, info.description),
+            None => "Connecting".to_string(),
+        };
+        if let Some((_, reason)) = &self.resync {
+            title.push_str(&format!(" (resynced: {reason})"));
+        }
+        if let Some(reason) = &self.disconnected {
+            title.push_str(&format!(" (disconnected: {reason})"));
+        }
+        if let Some((_, reason)) = &self.warning {
+            title.push_str(&format!(" (warning: {reason})"));
+        }
+        if self.audit_log.is_some() {
+            title.push_str(" (audit log, press any key to close)");
+        }
+        frame.render_widget(Block::default().title(title), title_view);
+
+        // motd banner
+        let banner_view = split[2];
+        let banner: Vec<Spans> = motd
+            .into_iter()
+            .map(|line| Span::styled(line, Style::default().fg(Color::Yellow)).into())
+            .collect();
+        frame.render_widget(Paragraph::new(banner), banner_view);
 
         // messages
-        let message_view = split[2];
+        let message_view = split[3];
         frame.render_widget(Block::default(), message_view);
 
+        if let Some(entries) = &self.audit_log {
+            frame.render_widget(Paragraph::new(audit_lines(entries)), message_view);
+            return;
+        }
+
+        let selected = match self.focus {
+            Focus::Chat { idx } => Some(idx),
+            Focus::Input { .. } => None,
+        };
         let mut messages = self
             .messages
             .iter()
             .rev()
-            .filter_map(|m| Some((m, self.all_messages.get(&m.sender_id)?)))
-            .filter_map(|(m, sender)| Some((m, sender.get(&m.message_id)?)));
+            .enumerate()
+            .filter_map(|(i, m)| Some((i, m, self.all_messages.get(&m.sender_id)?)))
+            .filter_map(|(i, m, sender)| Some((i, m, sender.get(&m.message_id)?)))
+            .peekable();
 
         let mut message_buffer: Vec<Spans> = vec![];
         let mut last_sender = None;
-        while let Some((message, message_str)) = messages.next() {
+        while let Some((i, message, message_str)) = messages.next() {
+            // newer messages are above it, so nothing new if it is the newest
+            if self.divider == Some(message.message_id) && i != 0 {
+                let line = if self.no_unicode { "-" } else { "─" }.repeat(4);
+                message_buffer.push(vec![].into());
+                message_buffer.push(
+                    Span::styled(
+                        format!("{line} new messages {line}"),
+                        Style::default().fg(Color::LightRed),
+                    )
+                    .into(),
+                );
+                last_sender = None;
+            }
+
+            if let MessageState::Notice(kind) = message.state {
+                let join_line = is_join_line(&message.state);
+                if join_line && self.join_lines == JoinLines::Hide {
+                    continue;
+                }
+
+                let (prefix, mut style) = self.notice_style(kind);
+                let mut text = format!("{prefix} {message_str}");
+                let mut is_selected = selected == Some(i);
+
+                if join_line && self.join_lines == JoinLines::Collapse {
+                    let (mut joins, mut leaves) = (0, 0);
+                    let mut count = |state: &MessageState| match state {
+                        MessageState::Notice(Some(NoticeKind::Join)) => joins += 1,
+                        _ => leaves += 1,
+                    };
+                    count(&message.state);
+                    while let Some((i, older, _)) =
+                        messages.next_if(|(_, older, _)| is_join_line(&older.state))
+                    {
+                        count(&older.state);
+                        is_selected |= selected == Some(i);
+                    }
+
+                    if joins + leaves > 1 {
+                        let (join, _) = self.notice_style(Some(NoticeKind::Join));
+                        let (leave, leave_style) = self.notice_style(Some(NoticeKind::Leave));
+                        text = [(join, joins, "joined"), (leave, leaves, "left")]
+                            .into_iter()
+                            .filter(|(_, count, _)| *count != 0)
+                            .map(|(prefix, count, what)| format!("{prefix} {count} {what}"))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        style = leave_style;
+                    }
+                }
+
+                if is_selected {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                message_buffer.push(vec![].into());
+                message_buffer.push(
+                    vec![
+                        Span::styled(text, style),
+                        Span::styled(
+                            format!(" {}", message.timestamp.format("%H:%M:%S")),
+                            Style::default()
+                                .fg(Color::White)
+                                .add_modifier(Modifier::ITALIC)
+                                .add_modifier(Modifier::DIM),
+                        ),
+                    ]
+                    .into(),
+                );
+                last_sender = None;
+                continue;
+            }
+
             if last_sender != Some(message.sender_id) {
                 message_buffer.push(vec![].into());
                 message_buffer.push(
@@ -295,22 +609,46 @@ impl App {
             }
             last_sender = Some(message.sender_id);
 
-            message_buffer.push(
-                vec![Span::styled(
-                    message_str.as_str(),
-                    Style::default().fg(Color::White),
-                )]
-                .into(),
-            );
+            let mut style = match message.state {
+                MessageState::Pending(_) => Style::default().fg(Color::Gray),
+                MessageState::Failed(_) => Style::default().fg(Color::LightRed),
+                MessageState::Received | MessageState::Sent | MessageState::Notice(_) => {
+                    Style::default().fg(Color::White)
+                }
+            };
+            if selected == Some(i) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            let mut line = vec![Span::styled(message_str.as_str(), style)];
+            line.extend(self.message_state_span(&message.state));
+            message_buffer.push(line.into());
         }
 
         frame.render_widget(Paragraph::new(message_buffer), message_view);
 
+        // typing
+        let typing_view = split[4];
+        frame.render_widget(
+            Paragraph::new(Span::styled(
+                self.typing_line(),
+                Style::default()
+                    .fg(Color::Gray)
+                    .add_modifier(Modifier::ITALIC),
+            )),
+            typing_view,
+        );
+
         // input
-        let input_view = split[4];
+        let input_view = split[6];
+        let prompt = if self.editing.is_some() {
+            "edit> "
+        } else {
+            "> "
+        };
         frame.render_widget(
             Paragraph::new(vec![vec![
-                Span::styled("> ", Style::default().fg(Color::White)),
+                Span::styled(prompt, Style::default().fg(Color::White)),
                 Span::styled(self.input.as_str(), Style::default().fg(Color::LightGreen)),
             ]
             .into()]),
@@ -318,41 +656,69 @@ impl App {
         );
         if let Focus::Input { idx } = self.focus {
             frame.set_cursor(
-                input_view.x + 2 + idx.try_into().unwrap_or(0_u16),
+                input_view.x + prompt.chars().count() as u16 + idx.try_into().unwrap_or(0_u16),
                 input_view.y,
             );
         }
     }
 
+    /// Line prefix and style of a notice
+    fn notice_style(&self, kind: Option<NoticeKind>) -> (&'static str, Style) {
+        let style = Style::default().add_modifier(Modifier::ITALIC);
+        match kind {
+            Some(NoticeKind::Join) if self.no_unicode => ("+", style.fg(Color::Green)),
+            Some(NoticeKind::Join) => ("→", style.fg(Color::Green)),
+            Some(NoticeKind::Leave) if self.no_unicode => ("-", style.fg(Color::DarkGray)),
+            Some(NoticeKind::Leave) => ("←", style.fg(Color::DarkGray)),
+            Some(NoticeKind::Moderation) => ("*", style.fg(Color::Yellow)),
+            Some(NoticeKind::Broadcast) => (
+                "! Server:",
+                Style::default()
+                    .fg(Color::LightMagenta)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            _ => ("*", style.fg(Color::Gray)),
+        }
+    }
+
+    fn message_state_span(&self, state: &MessageState) -> Option<Span<'static>> {
+        let (symbol, ascii) = match state {
+            MessageState::Received | MessageState::Notice(_) => return None,
+            MessageState::Pending(_) => (" …".to_string(), " ...".to_string()),
+            MessageState::Sent => (" ✓".to_string(), " ok".to_string()),
+            MessageState::Failed(reason) => (
+                format!(" ✗ {reason} (Tab, r to retry)"),
+                format!(" x {reason} (Tab, r to retry)"),
+            ),
+        };
+        let color = match state {
+            MessageState::Failed(_) => Color::LightRed,
+            _ => Color::Gray,
+        };
+
+        Some(Span::styled(
+            if self.no_unicode { ascii } else { symbol },
+            Style::default().fg(color).add_modifier(Modifier::DIM),
+        ))
+    }
+
     async fn tick(&mut self) {
-        match self.recv.try_recv() {
-            Ok(ServerPacket::Chat(ServerChatPacket::NewMessage {
-                sender_id,
-                message_id,
-                message,
-            })) => {
-                if self.self_id != SelfUuid::Some(sender_id) {
-                    let notify = format!("{sender_id}:\n{message}");
-                    let _ = Notification::new()
-                        .summary("Message")
-                        .body(notify.as_str())
-                        .show();
-                }
+        while let Ok(packet) = self.recv.try_recv() {
+            self.handle_packet(packet).await;
+        }
 
-                self.all_messages
-                    .entry(sender_id)
-                    .or_default()
-                    .insert(message_id, message);
-                self.messages.push(Message {
-                    sender_id,
-                    message_id,
-                    timestamp: Local::now(),
-                });
-            }
-            Ok(ServerPacket::Chat(ServerChatPacket::SelfMember { member_id })) => {
-                self.self_id = SelfUuid::Some(member_id);
+        if matches!(self.resync, Some((i, _)) if i.elapsed() >= RESYNC_NOTICE) {
+            self.resync = None;
+        }
+        if matches!(self.warning, Some((i, _)) if i.elapsed() >= WARNING_NOTICE) {
+            self.warning = None;
+        }
+        self.typing.retain(|_, i| i.elapsed() < TYPING_TIMEOUT);
+
+        for message in self.messages.iter_mut() {
+            if matches!(message.state, MessageState::Pending(i) if i.elapsed() >= PENDING_TIMEOUT) {
+                message.state = MessageState::Failed("No response from the server".to_string());
             }
-            _ => (),
         }
 
         match self.self_id {
@@ -374,7 +740,599 @@ impl App {
         }
     }
 
+    async fn handle_packet(&mut self, packet: ServerPacket) {
+        match packet {
+            ServerPacket::Chat(ServerChatPacket::NewMessage {
+                sender_id,
+                message_id,
+                message,
+            }) => {
+                if let Some(own) = self.message_mut(message_id) {
+                    // own message echoed back by the server
+                    let old_sender = own.sender_id;
+                    own.sender_id = sender_id;
+                    own.state = MessageState::Sent;
+                    if let Some(sender) = self.all_messages.get_mut(&old_sender) {
+                        sender.remove(&message_id);
+                    }
+                } else {
+                    self.typing.remove(&sender_id);
+                    if self.self_id != SelfUuid::Some(sender_id) {
+                        let notify = format!("{sender_id}:\n{message}");
+                        let _ = Notification::new()
+                            .summary("Message")
+                            .body(notify.as_str())
+                            .show();
+                    }
+
+                    self.messages.push(Message {
+                        sender_id,
+                        message_id,
+                        timestamp: Local::now(),
+                        state: MessageState::Received,
+                    });
+                }
+
+                self.all_messages
+                    .entry(sender_id)
+                    .or_default()
+                    .insert(message_id, message);
+            }
+            ServerPacket::Chat(ServerChatPacket::ServerInfo {
+                name,
+                description,
+                motd,
+            }) => {
+                self.server_info = Some(ServerInfo {
+                    name,
+                    description,
+                    motd,
+                });
+            }
+            ServerPacket::Chat(ServerChatPacket::SelfMember { member_id }) => {
+                self.self_id = SelfUuid::Some(member_id);
+                for joined in mem::take(&mut self.early_joins) {
+                    if joined != member_id {
+                        self.push_notice(Some(NoticeKind::Join), format!("{joined} joined"));
+                    }
+                }
+
+                // (re)sync everything else now that the server knows us
+                for request in [
+                    ClientChatPacket::RequestMembers,
+                    ClientChatPacket::RequestHistory,
+                ] {
+                    let _ = self.send.send(ClientPacket::Chat(request)).await;
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::Members { member_ids }) => {
+                self.members = member_ids.into_iter().collect();
+            }
+            ServerPacket::Chat(ServerChatPacket::NewMember { member_id }) => {
+                if self.members.insert(member_id) {
+                    match self.self_id {
+                        SelfUuid::Some(self_id) if self_id == member_id => {}
+                        SelfUuid::Some(_) => {
+                            self.push_notice(Some(NoticeKind::Join), format!("{member_id} joined"))
+                        }
+                        _ => self.early_joins.push(member_id),
+                    }
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::RemoveMember { member_id, reason }) => {
+                self.members.remove(&member_id);
+                self.early_joins.retain(|joined| *joined != member_id);
+                self.roles.remove(&member_id);
+                self.typing.remove(&member_id);
+                let reason = reason
+                    .map(|reason| format!(" ({reason})"))
+                    .unwrap_or_default();
+                self.push_notice(Some(NoticeKind::Leave), format!("{member_id} left{reason}"));
+            }
+            ServerPacket::Chat(ServerChatPacket::MemberRoles { roles }) => {
+                self.roles.extend(roles);
+            }
+            ServerPacket::Chat(ServerChatPacket::EditMessage {
+                sender_id,
+                message_id,
+                message,
+            }) => {
+                if let Some(text) = self
+                    .all_messages
+                    .get_mut(&sender_id)
+                    .and_then(|sender| sender.get_mut(&message_id))
+                {
+                    *text = message;
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::RemoveMessage {
+                sender_id,
+                message_id,
+            }) => {
+                if self.read_marker.message_id == Some(message_id) {
+                    // keep the unread count, the marker moves to the message before it
+                    let previous = self
+                        .messages
+                        .iter()
+                        .take_while(|m| m.message_id != message_id)
+                        .filter(|m| matches!(m.state, MessageState::Received | MessageState::Sent))
+                        .last()
+                        .map(|m| m.message_id);
+                    self.read_marker.message_id = previous;
+                }
+                self.messages.retain(|m| m.message_id != message_id);
+                if let Some(sender) = self.all_messages.get_mut(&sender_id) {
+                    sender.remove(&message_id);
+                }
+                if let Focus::Chat { idx } = &mut self.focus {
+                    *idx = self.messages.len().saturating_sub(1).min(*idx);
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::History { messages }) => {
+                self.load_history(messages);
+
+                match self.read_marker.message_id {
+                    // nothing to catch up on the first time on a server
+                    None => {
+                        if let Some(newest) = self.newest_read_candidate() {
+                            self.set_read_marker(newest);
+                            self.read_marker_guessed = true;
+                        }
+                    }
+                    Some(message_id) => {
+                        self.skip_own_messages().await;
+                        self.divider = (self.unread() != 0).then_some(message_id);
+                    }
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::InvalidState { reason }) => {
+                // the handler redoes the handshake,
+                // everything else is requested again
+                // after the new self member arrives
+                self.resync = Some((Instant::now(), reason.to_string()));
+                self.self_id = SelfUuid::None;
+                self.early_joins.clear();
+                self.members.clear();
+            }
+            ServerPacket::Chat(ServerChatPacket::Disconnect { reason }) => {
+                self.disconnected = Some(reason.to_string());
+                self.members.clear();
+            }
+            ServerPacket::Chat(ServerChatPacket::Warning { reason }) => {
+                self.warning = Some((Instant::now(), reason.to_string()));
+            }
+            ServerPacket::Chat(ServerChatPacket::Ack { message_id }) => {
+                self.commands.remove(&message_id);
+                if self.changes.remove(&message_id).is_none() {
+                    if let Some(message) = self.message_mut(message_id) {
+                        message.state = MessageState::Sent;
+                    }
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::Reject { message_id, reason }) => {
+                if self.commands.remove(&message_id).is_some() {
+                    self.push_notice(None, format!("Command failed: {reason}"));
+                } else if let Some(change) = self.changes.remove(&message_id) {
+                    // the message itself is unchanged, the server only applies accepted changes
+                    let change = match change {
+                        MessageChange::Edit => "Edit",
+                        MessageChange::Remove => "Removing the message",
+                    };
+                    self.push_notice(None, format!("{change} failed: {reason}"));
+                } else if let Some(message) = self.message_mut(message_id) {
+                    message.state = MessageState::Failed(reason.to_string());
+                }
+            }
+            ServerPacket::Chat(ServerChatPacket::AuditLog { entries }) => {
+                self.commands
+                    .retain(|_, command| !matches!(command, Command::AuditLog));
+                self.audit_log = Some(entries);
+            }
+            ServerPacket::Chat(ServerChatPacket::Moderation {
+                moderator_id,
+                member_id,
+                action,
+            }) => {
+                let notice = action.notice(member_id, moderator_id);
+                self.push_notice(Some(NoticeKind::Moderation), notice);
+            }
+            ServerPacket::Chat(ServerChatPacket::Notice { kind, message }) => {
+                self.push_notice(Some(kind), message);
+            }
+            ServerPacket::Chat(ServerChatPacket::Typing { member_id })
+                if self.self_id != SelfUuid::Some(member_id) =>
+            {
+                self.typing.insert(member_id, Instant::now());
+            }
+            ServerPacket::Chat(ServerChatPacket::ReadMarker { message_id })
+                if self.read_marker.sync =>
+            {
+                // only if the account read further on another client
+                let position = |id: Uuid| self.messages.iter().position(|m| m.message_id == id);
+                let local = self.read_marker.message_id.and_then(position);
+                let remote = position(message_id);
+                if remote > local || (self.read_marker_guessed && remote.is_some()) {
+                    self.set_read_marker(message_id);
+                    self.divider = (self.unread() != 0).then_some(message_id);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Replace the messages with the server history,
+    /// own messages not in the history are kept
+    fn load_history(&mut self, history: Vec<HistoryMessage>) {
+        let mut all_messages: HashMap<Uuid, HashMap<Uuid, String>> = HashMap::new();
+        let mut messages = Vec::with_capacity(history.len());
+
+        // own messages from before a reconnect have the old member id
+        let own: HashSet<Uuid> = self
+            .messages
+            .iter()
+            .filter(|m| m.state == MessageState::Sent)
+            .map(|m| m.message_id)
+            .collect();
+
+        for HistoryMessage {
+            sender_id,
+            message_id,
+            message,
+            timestamp,
+        } in history
+        {
+            all_messages
+                .entry(sender_id)
+                .or_default()
+                .insert(message_id, message);
+            messages.push(Message {
+                sender_id,
+                message_id,
+                timestamp: timestamp.into(),
+                state: if self.self_id == SelfUuid::Some(sender_id) || own.contains(&message_id) {
+                    MessageState::Sent
+                } else {
+                    MessageState::Received
+                },
+            });
+        }
+
+        for message in self.messages.drain(..) {
+            if !matches!(
+                message.state,
+                MessageState::Pending(_) | MessageState::Failed(_) | MessageState::Notice(_)
+            ) || messages.iter().any(|m| m.message_id == message.message_id)
+            {
+                continue;
+            }
+
+            if let Some(text) = self
+                .all_messages
+                .get_mut(&message.sender_id)
+                .and_then(|sender| sender.remove(&message.message_id))
+            {
+                all_messages
+                    .entry(message.sender_id)
+                    .or_default()
+                    .insert(message.message_id, text);
+                messages.push(message);
+            }
+        }
+
+        self.messages = messages;
+        self.all_messages = all_messages;
+    }
+
+    /// Show a line that isn't from a member
+    fn push_notice(&mut self, kind: Option<NoticeKind>, notice: String) {
+        let message_id = Uuid::new_v4();
+        self.all_messages
+            .entry(Uuid::nil())
+            .or_default()
+            .insert(message_id, notice);
+        self.messages.push(Message {
+            sender_id: Uuid::nil(),
+            message_id,
+            timestamp: Local::now(),
+            state: MessageState::Notice(kind),
+        });
+    }
+
+    /// The only member whose id starts with `prefix`
+    fn find_member(&self, prefix: &str) -> Result<Uuid, String> {
+        let prefix = prefix.to_lowercase();
+        let mut found = self
+            .members
+            .iter()
+            .filter(|member_id| member_id.to_string().starts_with(&prefix));
+        match (found.next(), found.count()) {
+            (Some(member_id), 0) => Ok(*member_id),
+            (Some(_), more) => Err(format!("`{prefix}` matches {} members", more + 1)),
+            (None, _) => Err(format!("No member matches `{prefix}`")),
+        }
+    }
+
+    /// Tell the server that the user is typing, throttled
+    async fn send_typing(&mut self) {
+        let command = self.input.trim_start().starts_with('/');
+        if command
+            || self.editing.is_some()
+            || self
+                .typing_sent
+                .is_some_and(|i| i.elapsed() < TYPING_INTERVAL)
+        {
+            return;
+        }
+
+        self.typing_sent = Some(Instant::now());
+        let _ = self
+            .send
+            .send(ClientPacket::Chat(ClientChatPacket::Typing))
+            .await;
+    }
+
+    /// `a and b are typing…`, empty if nobody is
+    fn typing_line(&self) -> String {
+        let ellipsis = if self.no_unicode { "..." } else { "…" };
+        let mut typing: Vec<String> = self.typing.keys().map(Uuid::to_string).collect();
+        typing.sort();
+
+        match typing.as_slice() {
+            [] => String::new(),
+            [member] => format!("{member} is typing{ellipsis}"),
+            [rest @ .., last] if typing.len() <= MAX_TYPING_NAMES => {
+                format!("{} and {last} are typing{ellipsis}", rest.join(", "))
+            }
+            members => format!("{} members are typing{ellipsis}", members.len()),
+        }
+    }
+
+    async fn run_command(&mut self, command: Result<Command, String>) {
+        let request_id = Uuid::new_v4();
+        let packet = command.and_then(|command| {
+            let packet = match &command {
+                Command::Moderate { member, action } => ClientChatPacket::Moderate {
+                    request_id,
+                    member_id: self.find_member(member)?,
+                    action: action.clone(),
+                },
+                Command::AuditLog => ClientChatPacket::RequestAuditLog { request_id },
+                Command::SlowMode(slow_mode) => ClientChatPacket::SetSlowMode {
+                    request_id,
+                    slow_mode: *slow_mode,
+                },
+                // settings don't need the server
+                Command::JoinLines => {
+                    self.cycle_join_lines();
+                    return Ok(None);
+                }
+            };
+            self.commands.insert(request_id, command);
+            Ok(Some(packet))
+        });
+
+        match packet {
+            Ok(Some(packet)) => {
+                let _ = self.send.send(ClientPacket::Chat(packet)).await;
+            }
+            Ok(None) => {}
+            Err(err) => self.push_notice(None, err),
+        }
+    }
+
+    /// Show, collapse, hide and show again
+    fn cycle_join_lines(&mut self) {
+        self.join_lines = match self.join_lines {
+            JoinLines::Show => JoinLines::Collapse,
+            JoinLines::Collapse => JoinLines::Hide,
+            JoinLines::Hide => JoinLines::Show,
+        };
+        let setting = match self.join_lines {
+            JoinLines::Show => "shown",
+            JoinLines::Collapse => "collapsed",
+            JoinLines::Hide => "hidden",
+        };
+        self.push_notice(None, format!("Join and leave lines are {setting}"));
+    }
+
+    /// Members are shown as members until the server sends the roles
+    fn role(&self, member_id: Uuid) -> Role {
+        self.roles.get(&member_id).copied().unwrap_or(Role::Member)
+    }
+
+    /// `idx` is counted from the newest message
+    fn selected_message(&self, idx: usize) -> Option<(Uuid, String)> {
+        let message = &self.messages[self.messages.len().checked_sub(idx + 1)?];
+        if let MessageState::Notice(_) = message.state {
+            return None;
+        }
+        let text = self
+            .all_messages
+            .get(&message.sender_id)?
+            .get(&message.message_id)?;
+        Some((message.message_id, text.clone()))
+    }
+
+    fn message_mut(&mut self, message_id: Uuid) -> Option<&mut Message> {
+        self.messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.message_id == message_id)
+    }
+
+    fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Input { .. } if !self.messages.is_empty() => Focus::Chat { idx: 0 },
+            Focus::Input { idx } => Focus::Input { idx },
+            Focus::Chat { .. } => Focus::Input {
+                idx: self.input.len(),
+            },
+        };
+    }
+
+    async fn send_message(&mut self, message_id: Uuid, message: String) {
+        let _ = self
+            .send
+            .send(ClientPacket::Chat(ClientChatPacket::SendMessage {
+                message_id,
+                message,
+            }))
+            .await;
+    }
+
+    /// Mark everything up to the newest message read,
+    /// the divider moves above the messages that were unread
+    async fn mark_read(&mut self) {
+        let Some(newest) = self.newest_read_candidate() else {
+            return;
+        };
+        if self.read_marker.message_id == Some(newest) {
+            return;
+        }
+
+        if self.unread() != 0 {
+            self.divider = self.read_marker.message_id;
+        }
+        self.set_read_marker(newest);
+
+        if self.read_marker.sync {
+            let _ = self
+                .send
+                .send(ClientPacket::Chat(ClientChatPacket::SetReadMarker {
+                    message_id: newest,
+                }))
+                .await;
+        }
+    }
+
+    fn set_read_marker(&mut self, message_id: Uuid) {
+        self.read_marker.message_id = Some(message_id);
+        self.read_marker_guessed = false;
+        if let Err(err) = self.read_marker.save() {
+            self.push_notice(None, format!("Failed to save the read marker: {err}"));
+        }
+    }
+
+    /// The newest message that the server knows about
+    fn newest_read_candidate(&self) -> Option<Uuid> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| matches!(m.state, MessageState::Received | MessageState::Sent))
+            .map(|m| m.message_id)
+    }
+
+    /// Messages from others after the read marker
+    fn unread(&self) -> usize {
+        self.messages[self.first_unread()..]
+            .iter()
+            .filter(|m| m.state == MessageState::Received && !self.is_own(m))
+            .count()
+    }
+
+    /// Index of the first message after the read marker
+    fn first_unread(&self) -> usize {
+        self.read_marker
+            .message_id
+            .and_then(|id| self.messages.iter().position(|m| m.message_id == id))
+            .map_or(0, |i| i + 1)
+    }
+
+    fn is_own(&self, message: &Message) -> bool {
+        message.state == MessageState::Sent || self.self_id == SelfUuid::Some(message.sender_id)
+    }
+
+    /// Move the read marker past own messages right after it
+    async fn skip_own_messages(&mut self) {
+        let newest_own = self.messages[self.first_unread()..]
+            .iter()
+            .filter(|m| matches!(m.state, MessageState::Received | MessageState::Sent))
+            .take_while(|m| self.is_own(m))
+            .last()
+            .map(|m| m.message_id);
+        let Some(newest_own) = newest_own else {
+            return;
+        };
+
+        self.set_read_marker(newest_own);
+        if self.read_marker.sync {
+            let _ = self
+                .send
+                .send(ClientPacket::Chat(ClientChatPacket::SetReadMarker {
+                    message_id: newest_own,
+                }))
+                .await;
+        }
+    }
+
+    /// Resend a failed message with the same id
+    async fn retry_message(&mut self, idx: usize) {
+        let Some(i) = self.messages.len().checked_sub(idx + 1) else {
+            return;
+        };
+        let message = &mut self.messages[i];
+        if !matches!(message.state, MessageState::Failed(_)) {
+            return;
+        }
+        message.state = MessageState::Pending(Instant::now());
+
+        let (sender_id, message_id) = (message.sender_id, message.message_id);
+        let Some(text) = self
+            .all_messages
+            .get(&sender_id)
+            .and_then(|sender| sender.get(&message_id))
+            .cloned()
+        else {
+            return;
+        };
+        self.send_message(message_id, text).await;
+    }
+
     async fn key_event(&mut self, event: KeyEvent) {
+        if self.audit_log.take().is_some() {
+            return;
+        }
+
+        if let Focus::Chat { idx } = &mut self.focus {
+            match event.code {
+                KeyCode::Up => {
+                    *idx = idx.saturating_sub(1);
+                }
+                KeyCode::Down => {
+                    *idx = self.messages.len().saturating_sub(1).min(*idx + 1);
+                }
+                KeyCode::Char('r') => {
+                    let idx = *idx;
+                    self.retry_message(idx).await;
+                }
+                KeyCode::Char('e') => {
+                    let idx = *idx;
+                    if let Some((message_id, text)) = self.selected_message(idx) {
+                        self.editing = Some(message_id);
+                        self.input = text;
+                        self.focus = Focus::Input {
+                            idx: self.input.len(),
+                        };
+                    }
+                }
+                KeyCode::Char('d') => {
+                    let idx = *idx;
+                    if let Some((message_id, _)) = self.selected_message(idx) {
+                        self.changes.insert(message_id, MessageChange::Remove);
+                        let _ = self
+                            .send
+                            .send(ClientPacket::Chat(ClientChatPacket::RemoveMessage {
+                                message_id,
+                            }))
+                            .await;
+                    }
+                }
+                KeyCode::Enter => self.toggle_focus(),
+                _ => {}
+            }
+            return;
+        }
+
         if let Focus::Input { idx } = &mut self.focus {
             match event.code {
                 // Doesn't work in crossterm yet
@@ -394,6 +1352,7 @@ impl App {
                 KeyCode::Char(ch) => {
                     self.input.insert(*idx, ch);
                     *idx = self.input.len().min(idx.saturating_add(1));
+                    self.send_typing().await;
                 }
                 KeyCode::Left => {
                     *idx = self.input.len().min(idx.saturating_sub(1));
@@ -418,15 +1377,46 @@ impl App {
                     self.focus = Focus::Input { idx: 0 };
                     let mut input = String::new();
                     swap(&mut input, &mut self.input);
+
+                    // the server broadcasts the edit back
+                    if let Some(message_id) = self.editing.take() {
+                        self.changes.insert(message_id, MessageChange::Edit);
+                        let _ = self
+                            .send
+                            .send(ClientPacket::Chat(ClientChatPacket::EditMessage {
+                                message_id,
+                                message: input,
+                            }))
+                            .await;
+                        return;
+                    }
+
+                    if let Some(command) = command::parse(&input) {
+                        self.run_command(command).await;
+                        return;
+                    }
+
                     let message_id = Uuid::new_v4();
 
-                    let _ = self
-                        .send
-                        .send(ClientPacket::Chat(ClientChatPacket::SendMessage {
-                            message_id,
-                            message: input.to_string(),
-                        }))
-                        .await;
+                    // shown as pending until the server acks it
+                    let sender_id = match self.self_id {
+                        SelfUuid::Some(id) => id,
+                        _ => Uuid::nil(),
+                    };
+                    self.all_messages
+                        .entry(sender_id)
+                        .or_default()
+                        .insert(message_id, input.clone());
+                    self.messages.push(Message {
+                        sender_id,
+                        message_id,
+                        timestamp: Local::now(),
+                        state: MessageState::Pending(Instant::now()),
+                    });
+                    // the user is caught up after answering
+                    self.divider = None;
+
+                    self.send_message(message_id, input).await;
                 }
                 _ => {}
             }
@@ -443,3 +1433,51 @@ impl App {
         Some(ch)
     }
 }
+
+fn role_color(role: Role) -> Color {
+    match role {
+        Role::Owner => Color::LightRed,
+        Role::Admin => Color::LightMagenta,
+        Role::Moderator => Color::LightGreen,
+        Role::Member => Color::LightCyan,
+        Role::Guest => Color::Gray,
+    }
+}
+
+fn is_join_line(state: &MessageState) -> bool {
+    matches!(
+        state,
+        MessageState::Notice(Some(NoticeKind::Join | NoticeKind::Leave))
+    )
+}
+
+/// Newest first, like the messages
+fn audit_lines(entries: &[AuditEntry]) -> Vec<Spans<'static>> {
+    if entries.is_empty() {
+        return vec![Span::styled("Nothing logged yet", Style::default().fg(Color::Gray)).into()];
+    }
+
+    entries
+        .iter()
+        .rev()
+        .map(|entry| {
+            let actor = entry
+                .actor
+                .map_or("server".to_string(), |actor| actor.member_id.to_string());
+            vec![
+                Span::styled(
+                    DateTime::<Local>::from(entry.timestamp)
+                        .format("%m-%d %H:%M:%S ")
+                        .to_string(),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::styled(
+                    format!("{actor} -> {}: ", entry.target.member_id),
+                    Style::default().fg(Color::LightCyan),
+                ),
+                Span::styled(entry.action.to_string(), Style::default().fg(Color::Yellow)),
+            ]
+            .into()
+        })
+        .collect()
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+//
+
+/// Secret token of an account
+///
+/// Only the client stores it, servers get
+/// a [`LoginToken`] derived from it instead.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountToken(pub Uuid);
+
+/// Secret login token of an account on one server
+///
+/// A server only sees its own login token, so it can't
+/// use the account on other servers. The server
+/// identifies the account by [`LoginToken::account_id`].
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginToken(Uuid);
+
+//
+
+/// Namespace of the account ids derived from the login tokens
+const ACCOUNT_NAMESPACE: Uuid = Uuid::from_u128(0x7a1cbd9e_5b3f_4c1e_9d2a_6e0f4b8c3a15);
+
+//
+
+impl AccountToken {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Login token for the server at `server`
+    pub fn login_token(&self, server: &str) -> LoginToken {
+        LoginToken(Uuid::new_v5(&self.0, server.as_bytes()))
+    }
+}
+
+impl LoginToken {
+    /// Public id of the account on this server, safe to share
+    pub fn account_id(&self) -> Uuid {
+        Uuid::new_v5(&ACCOUNT_NAMESPACE, self.0.as_bytes())
+    }
+}
+
+impl Display for AccountToken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// tokens end up in logs and packet traces through `Debug`
+
+impl Debug for AccountToken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AccountToken(<redacted>)")
+    }
+}
+
+impl Debug for LoginToken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "LoginToken(<redacted>)")
+    }
+}
+
+impl FromStr for AccountToken {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::from_str(s.trim()).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_tokens_differ_per_server() {
+        let token = AccountToken::generate();
+        let a = token.login_token("[::1]:13331");
+        let b = token.login_token("[::1]:13332");
+
+        assert_ne!(a, b);
+        assert_ne!(a.account_id(), b.account_id());
+        assert_eq!(
+            a.account_id(),
+            token.login_token("[::1]:13331").account_id()
+        );
+    }
+
+    #[test]
+    fn debug_hides_tokens() {
+        let token = AccountToken::generate();
+        let login = token.login_token("[::1]:13331");
+
+        assert!(!format!("{token:?}").contains(&token.0.to_string()));
+        assert!(!format!("{login:?}").contains(&login.0.to_string()));
+    }
+}
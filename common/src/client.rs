@@ -1,6 +1,13 @@
-use crate::{compat::CompatibilityInfo, FromPacketBytes, IntoPacketBytes};
+use crate::{
+    account::LoginToken, compat::CompatibilityInfo, duration::format_duration, role::Role,
+    FromPacketBytes, IntoPacketBytes,
+};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
 use uuid::Uuid;
 
 //
@@ -19,6 +26,12 @@ pub enum ClientPacket {
 pub enum ClientInitPacket {
     /// This first variant should never change
     ClientInfo(CompatibilityInfo),
+
+    /// [`ClientInitPacket::ClientInfo`] with an account
+    Login {
+        info: CompatibilityInfo,
+        token: LoginToken,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,11 +40,77 @@ pub enum ClientChatPacket {
     RequestMembers,
     RequestSelfMember,
 
-    SendMessage { message_id: Uuid, message: String },
-    EditMessage { message_id: Uuid, message: String },
-    RemoveMessage { message_id: Uuid },
+    SendMessage {
+        message_id: Uuid,
+        message: String,
+    },
+    EditMessage {
+        message_id: Uuid,
+        message: String,
+    },
+    RemoveMessage {
+        message_id: Uuid,
+    },
 
     KeepAlive,
+
+    RequestHistory,
+
+    /// Answered with an ack or a reject for `request_id`
+    Moderate {
+        request_id: Uuid,
+        member_id: Uuid,
+        action: ModerationAction,
+    },
+
+    /// Answered with [`crate::server::ServerChatPacket::AuditLog`]
+    /// or a reject for `request_id`
+    RequestAuditLog {
+        request_id: Uuid,
+    },
+
+    /// The user is typing a message,
+    /// sent every few seconds while they keep typing
+    Typing,
+
+    /// The newest message the user has read,
+    /// kept for the account if the client logged in
+    SetReadMarker {
+        message_id: Uuid,
+    },
+
+    /// Change the slow mode of the chat channel, `None` turns it off
+    ///
+    /// Answered with an ack or a reject for `request_id`
+    SetSlowMode {
+        request_id: Uuid,
+        slow_mode: Option<Duration>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ModerationAction {
+    Kick {
+        reason: String,
+    },
+
+    /// `None` unmutes
+    Mute {
+        duration: Option<Duration>,
+    },
+
+    /// Bans the address and the account,
+    /// `None` is a permanent ban
+    Ban {
+        reason: String,
+        duration: Option<Duration>,
+    },
+
+    /// Only for members with an account
+    SetRole {
+        role: Role,
+    },
 }
 
 //
@@ -51,3 +130,65 @@ impl IntoPacketBytes for ClientChatPacket {
 }
 
 impl FromPacketBytes for ClientPacket {}
+
+impl From<ClientInitPacket> for ClientPacket {
+    fn from(packet: ClientInitPacket) -> Self {
+        Self::Init(packet)
+    }
+}
+
+impl From<ClientChatPacket> for ClientPacket {
+    fn from(packet: ClientChatPacket) -> Self {
+        Self::Chat(packet)
+    }
+}
+
+impl ModerationAction {
+    /// Chat line about the action, like `<member> was kicked by <moderator>: <reason>`
+    pub fn notice(&self, member: impl Display, moderator: impl Display) -> String {
+        match self {
+            ModerationAction::Kick { reason } => {
+                format!("{member} was kicked by {moderator}: {reason}")
+            }
+            ModerationAction::Mute {
+                duration: Some(duration),
+            } => format!(
+                "{member} was muted for {} by {moderator}",
+                format_duration(*duration)
+            ),
+            ModerationAction::Mute { duration: None } => {
+                format!("{member} was unmuted by {moderator}")
+            }
+            ModerationAction::Ban { reason, duration } => format!(
+                "{member} was banned{} by {moderator}: {reason}",
+                duration
+                    .map(|duration| format!(" for {}", format_duration(duration)))
+                    .unwrap_or_default()
+            ),
+            ModerationAction::SetRole { role } => {
+                format!("{member} is now {role}, set by {moderator}")
+            }
+        }
+    }
+}
+
+impl Display for ModerationAction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ModerationAction::Kick { reason } => write!(f, "kick: {reason}"),
+            ModerationAction::Mute {
+                duration: Some(duration),
+            } => write!(f, "mute for {}", format_duration(*duration)),
+            ModerationAction::Mute { duration: None } => write!(f, "unmute"),
+            ModerationAction::Ban {
+                reason,
+                duration: Some(duration),
+            } => write!(f, "ban for {}: {reason}", format_duration(*duration)),
+            ModerationAction::Ban {
+                reason,
+                duration: None,
+            } => write!(f, "permanent ban: {reason}"),
+            ModerationAction::SetRole { role } => write!(f, "set role {role}"),
+        }
+    }
+}
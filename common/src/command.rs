@@ -0,0 +1,9 @@
+//! Helpers for the typed chat and admin commands
+
+/// The first word and the trimmed rest
+pub fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    s.split_once(char::is_whitespace)
+        .map(|(word, rest)| (word, rest.trim()))
+        .unwrap_or((s, ""))
+}
@@ -4,23 +4,24 @@ use thiserror::Error;
 
 //
 
-pub static COMPAT_INFO: CompatibilityInfo = CompatibilityInfo {
-    magic_bytes: MagicBytes(0x3064396a3df83f1d),
-    version: Version([0, 1, 0]),
-};
+pub static MAGIC_BYTES: MagicBytes = MagicBytes(0x3064396a3df83f1d);
+
+/// Bump the major version when an existing packet changes,
+/// the default [`VersionMismatchPolicy`] only compares that one
+pub static COMPAT_INFO: CompatibilityInfo = CompatibilityInfo::new(MAGIC_BYTES, Version([1, 0, 0]));
 
 //
 
 /// This should never change
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MagicBytes(pub u64);
-/// This should never change
 
+/// This should never change
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Version(pub [u16; 3]);
 
 /// This struct should never change
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CompatibilityInfo {
     /// These magic bytes are here
     /// to filter out accidental
@@ -53,7 +54,8 @@ pub struct CompatibilityInfo {
     version: Version,
 }
 
-/*#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum VersionMismatchPolicy {
     /// Major versions have to match.
     ///
@@ -76,7 +78,7 @@ pub enum VersionMismatchPolicy {
     /// as patches shouldn't contain
     /// any breaking changes.
     Same,
-}*/
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Error)]
 #[non_exhaustive]
@@ -97,6 +99,13 @@ impl Display for Version {
 }
 
 impl CompatibilityInfo {
+    pub const fn new(magic_bytes: MagicBytes, version: Version) -> Self {
+        Self {
+            magic_bytes,
+            version,
+        }
+    }
+
     /// Test if the connection `self` is compatible
     /// with the connection `other`.
     ///
@@ -107,13 +116,20 @@ impl CompatibilityInfo {
     /// Err is not
     pub fn compatible(
         self,
-        other: Self, /*policy: VersionMismatchPolicy*/
+        other: Self,
+        policy: VersionMismatchPolicy,
     ) -> Result<(), CompatibilityError> {
         if self.magic_bytes != other.magic_bytes {
             return Err(CompatibilityError::InvalidClient);
         }
 
-        if self.version.0[0] != other.version.0[0] {
+        let compatible = match policy {
+            VersionMismatchPolicy::SameMajor => self.version.0[0] == other.version.0[0],
+            VersionMismatchPolicy::SameMinor => self.version.0[0..2] == other.version.0[0..2],
+            VersionMismatchPolicy::Same => self.version == other.version,
+        };
+
+        if !compatible {
             return Err(CompatibilityError::VersionMismatch {
                 server: self.version,
                 client: other.version,
@@ -121,11 +137,60 @@ impl CompatibilityInfo {
         }
 
         Ok(())
+    }
+}
 
-        /* match policy {
-            VersionMismatchPolicy::SameMajor => self.version.0[0] == other.version.0[0],
-            VersionMismatchPolicy::SameMinor => self.version.0[0..2] == other.version.0[0..2],
-            VersionMismatchPolicy::Same => self.version == other.version,
-        } */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: CompatibilityInfo = CompatibilityInfo::new(MAGIC_BYTES, Version([1, 2, 3]));
+
+    const POLICIES: [VersionMismatchPolicy; 3] = [
+        VersionMismatchPolicy::SameMajor,
+        VersionMismatchPolicy::SameMinor,
+        VersionMismatchPolicy::Same,
+    ];
+
+    #[test]
+    fn version_mismatch_policies() {
+        let client = |version| CompatibilityInfo::new(MAGIC_BYTES, Version(version));
+
+        // compatible with `same-major`, `same-minor` and `same`
+        let cases = [
+            ([1, 2, 3], [true, true, true]),
+            ([1, 2, 9], [true, true, false]),
+            ([1, 0, 3], [true, false, false]),
+            ([2, 2, 3], [false, false, false]),
+            ([0, 2, 3], [false, false, false]),
+        ];
+        for (version, expected) in cases {
+            for (policy, compatible) in POLICIES.into_iter().zip(expected) {
+                let result = SERVER.compatible(client(version), policy);
+                if compatible {
+                    assert_eq!(result, Ok(()), "{version:?} with {policy:?}");
+                } else {
+                    assert_eq!(
+                        result,
+                        Err(CompatibilityError::VersionMismatch {
+                            server: Version([1, 2, 3]),
+                            client: Version(version),
+                        }),
+                        "{version:?} with {policy:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_magic_bytes_are_rejected() {
+        let stranger = CompatibilityInfo::new(MagicBytes(1), Version([1, 2, 3]));
+        for policy in POLICIES {
+            assert_eq!(
+                SERVER.compatible(stranger, policy),
+                Err(CompatibilityError::InvalidClient)
+            );
+        }
     }
 }
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+//
+
+/// Duration units, the largest first
+const UNITS: [(char, u64); 4] = [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)];
+
+//
+
+/// `30s`, `5m`, `2h` or `7d`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration `{s}`, use for example 30s, 5m, 2h or 7d");
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let (_, secs) = UNITS
+        .into_iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(invalid)?;
+    let n: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+    n.checked_mul(secs)
+        .filter(|secs| *secs != 0)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// The largest unit that fits evenly, like [`parse_duration`]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    UNITS
+        .into_iter()
+        .find(|(_, unit)| secs != 0 && secs.is_multiple_of(*unit))
+        .map(|(name, unit)| format!("{}{name}", secs / unit))
+        .unwrap_or_else(|| format!("{duration:.0?}"))
+}
@@ -1,4 +1,3 @@
-use bincode::Options;
 use bytes::Bytes;
 use serde::{de::DeserializeOwned, Serialize};
 use std::mem::size_of;
@@ -6,27 +5,29 @@ use uuid::Uuid;
 
 //
 
+pub mod account;
 pub mod client;
+pub mod command;
 pub mod compat;
+pub mod duration;
+pub mod role;
 pub mod server;
+pub mod trace;
 
 //
 
 pub static MAX_MEMBERS: usize = u16::MAX as usize;
 /// maximum size of [`ServerChatPacket::Members`] packet + some extra
 pub static MAX_PACKET_BYTES: usize = 100 + size_of::<Uuid>() * 2 * MAX_MEMBERS;
+/// maximum number of characters in a single message
+pub static MAX_MESSAGE_LENGTH: usize = 2000;
 
 //
 
 pub trait IntoPacketBytes: Serialize + Sized {
     fn into_bytes(self) -> Bytes {
-        bincode::DefaultOptions::new()
-            .with_limit(MAX_PACKET_BYTES as u64) // no support 128 bit operating systems unfortunately :(
-            .with_fixint_encoding()
-            .allow_trailing_bytes()
-            .serialize(&self)
-            .map(|v| Bytes::from(v))
-            .unwrap_or_default() // if encoding fails, just don't fucking care
+        // if encoding fails, just don't fucking care
+        codec::encode(&self).map(Bytes::from).unwrap_or_default()
     }
 }
 
@@ -35,11 +36,51 @@ pub trait FromPacketBytes: DeserializeOwned {
         // if decoding fails, we report is as an invalid packet
         // and possibly kick the client
 
+        codec::decode(&bytes[..])
+    }
+}
+
+//
+
+#[cfg(not(feature = "debug-codec"))]
+mod codec {
+    use crate::MAX_PACKET_BYTES;
+    use bincode::Options;
+    use serde::{de::DeserializeOwned, Serialize};
+
+    fn options() -> impl Options {
         bincode::DefaultOptions::new()
-            .with_limit(MAX_PACKET_BYTES as u64)
+            .with_limit(MAX_PACKET_BYTES as u64) // no support 128 bit operating systems unfortunately :(
             .with_fixint_encoding()
             .allow_trailing_bytes()
-            .deserialize(&bytes[..])
+    }
+
+    pub fn encode<T: Serialize>(value: &T) -> Option<Vec<u8>> {
+        options().serialize(value).ok()
+    }
+
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+        options().deserialize(bytes).ok()
+    }
+}
+
+/// Human readable JSON encoding for debugging the packet stream
+#[cfg(feature = "debug-codec")]
+mod codec {
+    use crate::MAX_PACKET_BYTES;
+    use serde::{de::DeserializeOwned, Serialize};
+
+    pub fn encode<T: Serialize>(value: &T) -> Option<Vec<u8>> {
+        serde_json::to_vec(value)
             .ok()
+            .filter(|bytes| bytes.len() <= MAX_PACKET_BYTES)
+    }
+
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+        if bytes.len() > MAX_PACKET_BYTES {
+            return None;
+        }
+
+        serde_json::from_slice(bytes).ok()
     }
 }
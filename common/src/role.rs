@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+//
+
+/// From the least to the most trusted,
+/// every role has the permissions of the roles below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Connected without an account
+    Guest,
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Permission {
+    SendMessages,
+    EditOwnMessages,
+    EditOthersMessages,
+    DeleteOwnMessages,
+    DeleteOthersMessages,
+    Kick,
+    Mute,
+    Ban,
+    ManageChannels,
+    ManageRoles,
+    ViewAuditLog,
+}
+
+//
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        self >= permission.min_role()
+    }
+}
+
+impl Permission {
+    /// The least trusted role with this permission
+    pub fn min_role(self) -> Role {
+        match self {
+            Permission::SendMessages => Role::Guest,
+            Permission::EditOwnMessages | Permission::DeleteOwnMessages => Role::Member,
+            Permission::DeleteOthersMessages | Permission::Kick | Permission::Mute => {
+                Role::Moderator
+            }
+            Permission::EditOthersMessages
+            | Permission::Ban
+            | Permission::ManageRoles
+            | Permission::ViewAuditLog => Role::Admin,
+            Permission::ManageChannels => Role::Owner,
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Permission::SendMessages => "send messages",
+            Permission::EditOwnMessages => "edit own messages",
+            Permission::EditOthersMessages => "edit messages of others",
+            Permission::DeleteOwnMessages => "delete own messages",
+            Permission::DeleteOthersMessages => "delete messages of others",
+            Permission::Kick => "kick",
+            Permission::Mute => "mute",
+            Permission::Ban => "ban",
+            Permission::ManageChannels => "manage channels",
+            Permission::ManageRoles => "manage roles",
+            Permission::ViewAuditLog => "view the audit log",
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "guest" => Ok(Role::Guest),
+            "member" => Ok(Role::Member),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err(format!(
+                "`{s}` is not a role (guest, member, moderator, admin or owner)"
+            )),
+        }
+    }
+}
@@ -1,12 +1,18 @@
 use crate::{
+    client::ModerationAction,
     compat::{CompatibilityError, CompatibilityInfo},
+    duration::format_duration,
+    role::{Permission, Role},
     FromPacketBytes, IntoPacketBytes,
 };
 use bytes::Bytes;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
 use uuid::Uuid;
@@ -42,8 +48,13 @@ pub enum ServerInitPacket {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum ServerChatPacket {
+    /// Sent after [`ServerInitPacket::Success`]
+    /// and when the server config changes
     ServerInfo {
         name: String,
+        description: String,
+        /// Message of the day
+        motd: String,
     },
 
     // member packets
@@ -56,8 +67,11 @@ pub enum ServerChatPacket {
     NewMember {
         member_id: Uuid,
     },
+    /// `reason` is `None` if the client closed
+    /// the connection or stopped responding
     RemoveMember {
         member_id: Uuid,
+        reason: Option<DisconnectReason>,
     },
     MemberInfo {
         members: HashMap<Uuid, MemberInfo>,
@@ -81,7 +95,118 @@ pub enum ServerChatPacket {
 
     KeepAlive,
 
-    InvalidState,
+    /// The server got a packet it didn't expect.
+    ///
+    /// The server goes back to the init state
+    /// after sending this and the client should
+    /// redo the handshake and request everything
+    /// (self member, members, history) again.
+    InvalidState {
+        reason: InvalidStateReason,
+    },
+
+    // request acknowledgements
+    //
+    // `message_id` is the client generated id
+    // from the request being answered
+    Ack {
+        message_id: Uuid,
+    },
+    Reject {
+        message_id: Uuid,
+        reason: RejectReason,
+    },
+
+    /// Latest messages, oldest first
+    History {
+        messages: Vec<HistoryMessage>,
+    },
+
+    /// The server closes the connection after this
+    Disconnect {
+        reason: DisconnectReason,
+    },
+
+    /// Sent to a member that keeps hitting the rate limits
+    Warning {
+        reason: WarningReason,
+    },
+
+    /// Roles of the listed members, sent after
+    /// [`ServerChatPacket::Members`], [`ServerChatPacket::NewMember`]
+    /// and when roles change
+    MemberRoles {
+        roles: HashMap<Uuid, Role>,
+    },
+
+    /// A moderator acted on a member
+    Moderation {
+        moderator_id: Uuid,
+        member_id: Uuid,
+        action: ModerationAction,
+    },
+
+    /// Latest audit log entries, oldest first
+    AuditLog {
+        entries: Vec<AuditEntry>,
+    },
+
+    /// A line from the server itself, not from a member
+    Notice {
+        kind: NoticeKind,
+        message: String,
+    },
+
+    /// The member is typing, until its next message
+    /// or until nothing is heard for a few seconds
+    Typing {
+        member_id: Uuid,
+    },
+
+    /// The newest message the account has read on any client,
+    /// sent after the [`ServerChatPacket::History`] if there is one
+    ReadMarker {
+        message_id: Uuid,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryMessage {
+    pub sender_id: Uuid,
+    pub message_id: Uuid,
+    pub message: String,
+    pub timestamp: SystemTime,
+}
+
+/// A moderation action recorded by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: SystemTime,
+
+    /// `None` for the server itself (console, flood protection)
+    pub actor: Option<AuditMember>,
+
+    pub target: AuditMember,
+
+    pub action: AuditAction,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AuditMember {
+    pub member_id: Uuid,
+    pub account_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum AuditAction {
+    Moderation(ModerationAction),
+
+    /// A message removed by someone else than the sender
+    RemoveMessage {
+        message_id: Uuid,
+        message: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,6 +245,126 @@ pub enum ServerInitFailReason {
 
     #[error("Server message: {0}")]
     Custom(Cow<'static, str>),
+
+    #[error("Server is full")]
+    ServerFull,
+
+    #[error("Too many connections from your address (limit {limit})")]
+    TooManyConnections { limit: usize },
+
+    /// `expires` is `None` for permanent bans
+    #[error("Banned: {reason}{}", until(.expires))]
+    Banned {
+        reason: String,
+        expires: Option<SystemTime>,
+    },
+
+    #[error("Server is shutting down")]
+    ShuttingDown,
+}
+
+/// What a [`ServerChatPacket::Notice`] is about, clients style them differently
+///
+/// Clients also show joins and leaves from
+/// [`ServerChatPacket::NewMember`] and [`ServerChatPacket::RemoveMember`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum NoticeKind {
+    Join,
+    Leave,
+
+    /// Moderation by the server admin,
+    /// moderators show up in [`ServerChatPacket::Moderation`]
+    Moderation,
+
+    /// Announcement from the server admin
+    Broadcast,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+#[non_exhaustive]
+pub enum InvalidStateReason {
+    #[error("Got an init packet while chatting")]
+    UnexpectedInit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    #[error("Kicked: {reason}")]
+    Kicked { reason: String },
+
+    #[error("Banned: {reason}")]
+    Banned { reason: String },
+
+    #[error("Kicked for flooding")]
+    Flooding,
+
+    #[error("Too slow to keep up with the chat")]
+    Lagged,
+
+    /// `restart_in` is a rough estimate
+    #[error("Server shutting down{}", back_in(.restart_in))]
+    Shutdown { restart_in: Option<Duration> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+#[non_exhaustive]
+pub enum WarningReason {
+    #[error("Slow down, {warnings_left} warnings left before a mute")]
+    Flooding { warnings_left: u32 },
+
+    #[error("Muted for {} for flooding", format_duration(*.duration))]
+    FloodMuted { duration: Duration },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+#[non_exhaustive]
+pub enum RejectReason {
+    #[error("Not supported by the server")]
+    Unsupported,
+
+    #[error("Empty message")]
+    Empty,
+
+    #[error("Message too long (max {max} characters)")]
+    TooLong { max: usize },
+
+    #[error("Message contains control characters")]
+    ControlCharacters,
+
+    #[error("Message id already in use")]
+    DuplicateId,
+
+    #[error("Message contains a blocked word")]
+    Blocked,
+
+    #[error("Sending too fast, try again in {retry_after:.1?}")]
+    RateLimited { retry_after: Duration },
+
+    #[error("Muted for {}", format_duration(*.remaining))]
+    Muted { remaining: Duration },
+
+    #[error("Slow mode, try again in {retry_after:.1?}")]
+    SlowMode { retry_after: Duration },
+
+    #[error("Missing permission: {0}")]
+    MissingPermission(Permission),
+
+    #[error("No such message")]
+    UnknownMessage,
+
+    #[error("No such member")]
+    UnknownMember,
+
+    #[error("Can't moderate yourself or members with the same or a higher role")]
+    Outranked,
+
+    #[error("The member has no account")]
+    NoAccount,
+
+    #[error("Server message: {0}")]
+    Custom(Cow<'static, str>),
 }
 
 //
@@ -139,3 +384,43 @@ impl IntoPacketBytes for ServerChatPacket {
 }
 
 impl FromPacketBytes for ServerPacket {}
+
+impl From<ServerInitPacket> for ServerPacket {
+    fn from(packet: ServerInitPacket) -> Self {
+        Self::Init(packet)
+    }
+}
+
+impl From<ServerChatPacket> for ServerPacket {
+    fn from(packet: ServerChatPacket) -> Self {
+        Self::Chat(packet)
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AuditAction::Moderation(action) => action.fmt(f),
+            AuditAction::RemoveMessage { message, .. } => {
+                write!(f, "remove message: {message}")
+            }
+        }
+    }
+}
+
+/// ` (until <local time>)`, empty if it never expires
+fn until(expires: &Option<SystemTime>) -> String {
+    expires
+        .map(|expires| {
+            let expires = DateTime::<Local>::from(expires);
+            format!(" (until {})", expires.format("%Y-%m-%d %H:%M"))
+        })
+        .unwrap_or_default()
+}
+
+/// `, back in about <duration>`, empty if unknown
+fn back_in(restart_in: &Option<Duration>) -> String {
+    restart_in
+        .map(|restart_in| format!(", back in about {}", format_duration(restart_in)))
+        .unwrap_or_default()
+}
@@ -0,0 +1,60 @@
+use chrono::{SecondsFormat, Utc};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+//
+
+/// Logs every sent and received packet to a file
+///
+/// Cloning shares the same file.
+/// The default value traces nothing.
+#[derive(Debug, Clone, Default)]
+pub struct PacketTrace {
+    file: Option<Arc<Mutex<LineWriter<File>>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Recv,
+}
+
+//
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Direction::Send => write!(f, "send"),
+            Direction::Recv => write!(f, "recv"),
+        }
+    }
+}
+
+impl PacketTrace {
+    /// Append packet traces to the file at `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(LineWriter::new(file)))),
+        })
+    }
+
+    /// Write one line: `<timestamp> <direction> <peer> <packet>`
+    pub fn log(&self, direction: Direction, peer: SocketAddr, packet: &impl Debug) {
+        let Some(file) = self.file.as_ref() else {
+            return;
+        };
+
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        if let Ok(mut file) = file.lock() {
+            // tracing is best effort, a full disk shouldn't kill the connection
+            let _ = writeln!(file, "{timestamp} {direction} {peer} {packet:?}");
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! The JSON codec that replaces bincode with the `debug-codec` feature
+
+#![cfg(feature = "debug-codec")]
+
+use bytes::Bytes;
+use tui_chat_app_common::{
+    client::{ClientChatPacket, ClientPacket},
+    FromPacketBytes, IntoPacketBytes, MAX_PACKET_BYTES,
+};
+use uuid::Uuid;
+
+//
+
+fn send_message(message: String) -> ClientPacket {
+    ClientPacket::Chat(ClientChatPacket::SendMessage {
+        message_id: Uuid::nil(),
+        message,
+    })
+}
+
+#[test]
+fn round_trip() {
+    let bytes = send_message("hi".to_string()).into_bytes();
+
+    // readable in a packet trace
+    let json = std::str::from_utf8(&bytes).unwrap();
+    assert!(
+        json.contains("SendMessage") && json.contains("\"hi\""),
+        "{json}"
+    );
+
+    match ClientPacket::from_bytes(bytes.clone()) {
+        Some(ClientPacket::Chat(ClientChatPacket::SendMessage {
+            message_id,
+            message,
+        })) => {
+            assert_eq!(message_id, Uuid::nil());
+            assert_eq!(message, "hi");
+        }
+        other => panic!("decoded into {other:?}"),
+    }
+}
+
+#[test]
+fn invalid_json_is_rejected() {
+    assert!(ClientPacket::from_bytes(Bytes::from_static(b"{\"Chat\":")).is_none());
+    assert!(ClientPacket::from_bytes(Bytes::from_static(b"not json")).is_none());
+}
+
+#[test]
+fn packets_over_the_limit_are_rejected() {
+    // encoding fails and nothing is sent
+    let too_long = send_message("a".repeat(MAX_PACKET_BYTES));
+    assert!(too_long.into_bytes().is_empty());
+
+    // valid JSON, but padded past the limit
+    let mut bytes = send_message("hi".to_string()).into_bytes().to_vec();
+    bytes.resize(MAX_PACKET_BYTES, b' ');
+    assert!(ClientPacket::from_bytes(bytes.clone().into()).is_some());
+    bytes.push(b' ');
+    assert!(ClientPacket::from_bytes(bytes.into()).is_none());
+}
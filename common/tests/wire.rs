@@ -0,0 +1,199 @@
+//! Golden wire fixtures for the packets that should never change
+//!
+//! Old clients and servers have to be able to at least finish the
+//! handshake with new ones, so these byte layouts are frozen.
+//! If one of these tests fails, the wire format broke.
+
+#![cfg(not(feature = "debug-codec"))]
+
+use bytes::Bytes;
+use std::borrow::Cow;
+use tui_chat_app_common::{
+    client::{ClientInitPacket, ClientPacket},
+    compat::{CompatibilityError, CompatibilityInfo, MagicBytes, Version, MAGIC_BYTES},
+    server::{ServerInitFailReason, ServerInitPacket, ServerPacket},
+    FromPacketBytes, IntoPacketBytes,
+};
+
+//
+
+const INFO: CompatibilityInfo =
+    CompatibilityInfo::new(MagicBytes(0x0807060504030201), Version([1, 2, 3]));
+
+#[rustfmt::skip]
+const INFO_BYTES: [u8; 14] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // magic_bytes: u64
+    0x01, 0x00, 0x02, 0x00, 0x03, 0x00,             // version: [u16; 3]
+];
+
+/// `u32` little endian variant tags
+const TAG_0: [u8; 4] = [0, 0, 0, 0];
+const TAG_1: [u8; 4] = [1, 0, 0, 0];
+const TAG_2: [u8; 4] = [2, 0, 0, 0];
+const TAG_3: [u8; 4] = [3, 0, 0, 0];
+const TAG_4: [u8; 4] = [4, 0, 0, 0];
+
+fn fixture(parts: &[&[u8]]) -> Bytes {
+    parts.concat().into()
+}
+
+fn round_trip<T: IntoPacketBytes + FromPacketBytes + Clone>(golden: &Bytes) -> T {
+    let packet = T::from_bytes(golden.clone()).expect("golden fixture failed to decode");
+    let bytes = packet.clone().into_bytes();
+    assert_eq!(
+        &bytes, golden,
+        "re-encoded packet differs from the golden fixture"
+    );
+    packet
+}
+
+//
+
+#[test]
+fn magic_bytes() {
+    assert_eq!(MAGIC_BYTES, MagicBytes(0x3064396a3df83f1d));
+}
+
+#[test]
+fn client_init_client_info() {
+    let golden = fixture(&[&TAG_0, &TAG_0, &INFO_BYTES]);
+    assert_eq!(ClientInitPacket::ClientInfo(INFO).into_bytes(), golden);
+
+    match round_trip::<ClientPacket>(&golden) {
+        ClientPacket::Init(ClientInitPacket::ClientInfo(info)) => assert_eq!(info, INFO),
+        other => panic!("decoded into {other:?}"),
+    }
+}
+
+#[test]
+fn server_init_success() {
+    let golden = fixture(&[&TAG_0, &TAG_0, &INFO_BYTES]);
+    assert_eq!(ServerInitPacket::Success(INFO).into_bytes(), golden);
+
+    match round_trip::<ServerPacket>(&golden) {
+        ServerPacket::Init(ServerInitPacket::Success(info)) => assert_eq!(info, INFO),
+        other => panic!("decoded into {other:?}"),
+    }
+}
+
+#[test]
+fn server_init_fail_invalid_state() {
+    let golden = fixture(&[&TAG_0, &TAG_1, &TAG_0]);
+    let packet = ServerInitPacket::Fail {
+        reason: ServerInitFailReason::InvalidState,
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    assert!(matches!(
+        round_trip::<ServerPacket>(&golden),
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason: ServerInitFailReason::InvalidState
+        })
+    ));
+}
+
+#[test]
+fn server_init_fail_invalid_packet() {
+    let golden = fixture(&[&TAG_0, &TAG_1, &TAG_1]);
+    let packet = ServerInitPacket::Fail {
+        reason: ServerInitFailReason::InvalidPacket,
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    assert!(matches!(
+        round_trip::<ServerPacket>(&golden),
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason: ServerInitFailReason::InvalidPacket
+        })
+    ));
+}
+
+#[test]
+fn server_init_fail_invalid_client() {
+    let golden = fixture(&[&TAG_0, &TAG_1, &TAG_2, &TAG_0]);
+    let packet = ServerInitPacket::Fail {
+        reason: CompatibilityError::InvalidClient.into(),
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    assert!(matches!(
+        round_trip::<ServerPacket>(&golden),
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason: ServerInitFailReason::CompatibilityError(CompatibilityError::InvalidClient)
+        })
+    ));
+}
+
+#[test]
+fn server_init_fail_version_mismatch() {
+    #[rustfmt::skip]
+    let golden = fixture(&[
+        &TAG_0,
+        &TAG_1,
+        &TAG_2,
+        &TAG_1,
+        &[0x01, 0x00, 0x02, 0x00, 0x03, 0x00], // server: Version
+        &[0x04, 0x00, 0x05, 0x00, 0x06, 0x00], // client: Version
+    ]);
+    let packet = ServerInitPacket::Fail {
+        reason: CompatibilityError::VersionMismatch {
+            server: Version([1, 2, 3]),
+            client: Version([4, 5, 6]),
+        }
+        .into(),
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    match round_trip::<ServerPacket>(&golden) {
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason:
+                ServerInitFailReason::CompatibilityError(CompatibilityError::VersionMismatch {
+                    server,
+                    client,
+                }),
+        }) => {
+            assert_eq!(server, Version([1, 2, 3]));
+            assert_eq!(client, Version([4, 5, 6]));
+        }
+        other => panic!("decoded into {other:?}"),
+    }
+}
+
+#[test]
+fn server_init_fail_already_connected() {
+    let golden = fixture(&[&TAG_0, &TAG_1, &TAG_3]);
+    let packet = ServerInitPacket::Fail {
+        reason: ServerInitFailReason::AlreadyConnected,
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    assert!(matches!(
+        round_trip::<ServerPacket>(&golden),
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason: ServerInitFailReason::AlreadyConnected
+        })
+    ));
+}
+
+#[test]
+fn server_init_fail_custom() {
+    #[rustfmt::skip]
+    let golden = fixture(&[
+        &TAG_0,
+        &TAG_1,
+        &TAG_4,
+        &[0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // string length: u64
+        b"hi",
+    ]);
+    let packet = ServerInitPacket::Fail {
+        reason: ServerInitFailReason::Custom(Cow::Borrowed("hi")),
+    };
+    assert_eq!(packet.into_bytes(), golden);
+
+    match round_trip::<ServerPacket>(&golden) {
+        ServerPacket::Init(ServerInitPacket::Fail {
+            reason: ServerInitFailReason::Custom(message),
+        }) => assert_eq!(message, "hi"),
+        other => panic!("decoded into {other:?}"),
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{state::Shared, validate};
+use std::{
+    fmt::Write as _,
+    fs, io,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net,
+    },
+    path::Path,
+    process,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use tracing::{error, info};
+use tui_chat_app_common::{
+    client::ModerationAction,
+    command::split_word,
+    duration::parse_duration,
+    server::{NoticeKind, ServerChatPacket},
+};
+use uuid::Uuid;
+
+//
+
+pub const HELP: &str = "members, kick <member> [reason], ban <member> [duration] [reason], \
+    notice <message>, reload, stats, help";
+
+//
+
+/// Listen for admin commands on `admin.socket`
+///
+/// Every command is one line, answered with its output
+/// and then `ok` or `error: <reason>` on a line of its own
+pub async fn run(shared: Arc<Shared>) {
+    let Some(path) = shared.config().admin.socket.clone() else {
+        return;
+    };
+
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(path = %path.display(), "Failed to open the admin socket: {err}");
+            return;
+        }
+    };
+    info!(path = %path.display(), "Listening for admin commands");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(session(stream, shared.clone()));
+            }
+            Err(err) => {
+                error!("Failed to accept an admin connection: {err}");
+                tokio::time::sleep(Duration::SECOND).await;
+            }
+        }
+    }
+}
+
+/// Remove the socket file, called on shutdown
+pub fn remove_socket(shared: &Shared) {
+    if let Some(path) = &shared.config().admin.socket {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    // a server that didn't shut down cleanly leaves the socket file behind
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        if net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is using it",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    // `rename` would replace other files
+    if fs::symlink_metadata(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a file is in the way",
+        ));
+    }
+
+    // bound in a directory that only the server can enter and then
+    // moved in place, so that nobody can connect before the chmod
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let private = dir.join(format!(".admin-socket-{}", process::id()));
+    let _ = fs::remove_dir_all(&private);
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let tmp = private.join("socket");
+    let listener = UnixListener::bind(&tmp).and_then(|listener| {
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&tmp, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&private);
+    listener
+}
+
+async fn session(stream: UnixStream, shared: Arc<Shared>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        info!(command = line, "Admin command");
+        let response = match command(&shared, line) {
+            Ok(output) => output + "ok\n",
+            Err(err) => format!("error: {err}\n"),
+        };
+        if write.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// An admin command line, `member` is a member id or the start of one
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Members,
+    Kick {
+        member: &'a str,
+        reason: &'a str,
+    },
+    /// `None` is a permanent ban
+    Ban {
+        member: &'a str,
+        duration: Option<Duration>,
+        reason: &'a str,
+    },
+    Notice(&'a str),
+    Reload,
+    Stats,
+    Help,
+}
+
+fn command(shared: &Shared, line: &str) -> Result<String, String> {
+    let config = shared.config();
+
+    match parse(line)? {
+        Command::Members => Ok(members(shared)),
+        Command::Kick { member, reason } => {
+            let member_id = find_member(shared, member)?;
+            let reason = validate::reason(&config, reason).map_err(|err| err.to_string())?;
+            let reason = or_default(reason, "Kicked by the server admin");
+            shared.moderate(None, member_id, ModerationAction::Kick { reason });
+            Ok(String::new())
+        }
+        Command::Ban {
+            member,
+            duration,
+            reason,
+        } => {
+            let member_id = find_member(shared, member)?;
+            let reason = validate::reason(&config, reason).map_err(|err| err.to_string())?;
+            let reason = or_default(reason, "Banned by the server admin");
+            shared.moderate(None, member_id, ModerationAction::Ban { reason, duration });
+            Ok(String::new())
+        }
+        Command::Notice(message) => {
+            let message = validate::message(&config, message).map_err(|err| err.to_string())?;
+            let _ = shared.broadcast.send(
+                ServerChatPacket::Notice {
+                    kind: NoticeKind::Broadcast,
+                    message,
+                }
+                .into(),
+            );
+            Ok(String::new())
+        }
+        Command::Reload => shared
+            .reload_config()
+            .map(|()| String::new())
+            .map_err(|err| err.to_string()),
+        Command::Stats => Ok(stats(shared)),
+        Command::Help => Ok(format!("{HELP}\n")),
+    }
+}
+
+fn parse<'a>(line: &'a str) -> Result<Command<'a>, String> {
+    let (name, args) = split_word(line);
+    let required = |member: &'a str| {
+        if member.is_empty() {
+            Err(format!("Missing the member, try: {HELP}"))
+        } else {
+            Ok(member)
+        }
+    };
+
+    Ok(match name {
+        "members" => Command::Members,
+        "kick" => {
+            let (member, reason) = split_word(args);
+            Command::Kick {
+                member: required(member)?,
+                reason,
+            }
+        }
+        "ban" => {
+            let (member, rest) = split_word(args);
+            let member = required(member)?;
+            // the duration is optional
+            let (first, reason) = split_word(rest);
+            let (duration, reason) = match parse_duration(first) {
+                Ok(duration) => (Some(duration), reason),
+                Err(_) => (None, rest),
+            };
+            Command::Ban {
+                member,
+                duration,
+                reason,
+            }
+        }
+        "notice" => Command::Notice(args),
+        "reload" => Command::Reload,
+        "stats" => Command::Stats,
+        "help" => Command::Help,
+        _ => return Err(format!("Unknown command `{name}`, try: {HELP}")),
+    })
+}
+
+/// `<member id> <role> <address> <account id or -> <seconds online>`
+fn members(shared: &Shared) -> String {
+    let mut members: Vec<_> = shared
+        .members
+        .iter()
+        .map(|member| {
+            (
+                *member.key(),
+                member.addr,
+                member.account_id,
+                member.connected_at,
+            )
+        })
+        .collect();
+    members.sort_by_key(|(_, _, _, connected_at)| *connected_at);
+
+    let mut out = String::new();
+    for (member_id, addr, account_id, connected_at) in members {
+        let account_id = account_id
+            .map(|account_id| account_id.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let _ = writeln!(
+            out,
+            "{member_id} {} {addr} {account_id} {}",
+            shared.role(member_id),
+            connected_at.elapsed().as_secs()
+        );
+    }
+    out
+}
+
+/// `<name> <value>` per counter
+fn stats(shared: &Shared) -> String {
+    let stats = &shared.stats;
+    let counters = [
+        ("messages", &stats.messages),
+        ("packets_in", &stats.packets_in),
+        ("packets_out", &stats.packets_out),
+        ("bytes_in", &stats.bytes_in),
+        ("bytes_out", &stats.bytes_out),
+        ("lag_events", &stats.lag_events),
+        ("lagged_packets", &stats.lagged_packets),
+    ];
+
+    let mut out = format!("members {}\n", shared.members.len());
+    for (name, counter) in counters {
+        let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+    }
+    for (reason, count) in stats.handshake_failures() {
+        let _ = writeln!(out, "handshake_failures.{reason} {count}");
+    }
+    out
+}
+
+/// The only member whose id starts with `prefix`
+fn find_member(shared: &Shared, prefix: &str) -> Result<Uuid, String> {
+    let lowercase = prefix.to_lowercase();
+    let mut found = shared
+        .members
+        .iter()
+        .map(|member| *member.key())
+        .filter(|member_id| member_id.to_string().starts_with(&lowercase));
+    match (found.next(), found.next()) {
+        (Some(member_id), None) => Ok(member_id),
+        (None, _) => Err(format!("No member `{prefix}`")),
+        (Some(_), Some(_)) => Err(format!("More than one member starts with `{prefix}`")),
+    }
+}
+
+fn or_default(reason: String, default: &str) -> String {
+    if reason.is_empty() {
+        default.to_string()
+    } else {
+        reason
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("members"), Ok(Command::Members));
+        assert_eq!(parse("  stats "), Ok(Command::Stats));
+        assert_eq!(parse("reload"), Ok(Command::Reload));
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(
+            parse("notice  back in 5  minutes"),
+            Ok(Command::Notice("back in 5  minutes"))
+        );
+        assert_eq!(
+            parse("kick 3f2a spam"),
+            Ok(Command::Kick {
+                member: "3f2a",
+                reason: "spam"
+            })
+        );
+        assert_eq!(
+            parse("kick 3f2a"),
+            Ok(Command::Kick {
+                member: "3f2a",
+                reason: ""
+            })
+        );
+    }
+
+    #[test]
+    fn ban_duration_is_optional() {
+        assert_eq!(
+            parse("ban 3f2a 2h raid"),
+            Ok(Command::Ban {
+                member: "3f2a",
+                duration: Some(Duration::from_secs(2 * 60 * 60)),
+                reason: "raid"
+            })
+        );
+        assert_eq!(
+            parse("ban 3f2a raid again"),
+            Ok(Command::Ban {
+                member: "3f2a",
+                duration: None,
+                reason: "raid again"
+            })
+        );
+        assert_eq!(
+            parse("ban 3f2a"),
+            Ok(Command::Ban {
+                member: "3f2a",
+                duration: None,
+                reason: ""
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("kick").unwrap_err().starts_with("Missing the member"));
+        assert!(parse("ban  ")
+            .unwrap_err()
+            .starts_with("Missing the member"));
+        assert!(parse("shutdown")
+            .unwrap_err()
+            .starts_with("Unknown command"));
+        assert!(parse("").unwrap_err().starts_with("Unknown command"));
+    }
+
+    #[tokio::test]
+    async fn socket_is_private() {
+        let dir = TempDir::new();
+        let path = dir.join("admin.sock");
+
+        let _listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let files = fs::read_dir(&dir).unwrap().count();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(files, 1, "the private directory was left behind");
+    }
+}
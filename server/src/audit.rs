@@ -0,0 +1,43 @@
+use std::{collections::VecDeque, sync::Mutex};
+use tui_chat_app_common::server::AuditEntry;
+
+//
+
+/// Latest moderation actions, the
+/// whole log is only kept on disk
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    recent: Mutex<VecDeque<AuditEntry>>,
+}
+
+//
+
+/// Entries kept in memory and sent to clients
+pub const RECENT_ENTRIES: usize = 100;
+
+//
+
+impl AuditLog {
+    pub fn new(entries: Vec<AuditEntry>) -> Self {
+        let mut recent = VecDeque::from(entries);
+        if recent.len() > RECENT_ENTRIES {
+            recent.drain(..recent.len() - RECENT_ENTRIES);
+        }
+        Self {
+            recent: Mutex::new(recent),
+        }
+    }
+
+    pub fn push(&self, entry: AuditEntry) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= RECENT_ENTRIES {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    /// Oldest first
+    pub fn recent(&self) -> Vec<AuditEntry> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    str::FromStr,
+    sync::RwLock,
+    time::Duration,
+};
+use tui_chat_app_common::server::ServerInitFailReason;
+use uuid::Uuid;
+
+//
+
+/// Every active ban
+#[derive(Debug, Default)]
+pub struct Bans {
+    list: RwLock<Vec<Ban>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    #[serde(flatten)]
+    pub target: BanTarget,
+
+    pub reason: String,
+
+    /// `None` is a permanent ban
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// `ip = "10.0.0.0/8"` or `account = "<account id>"` in the ban file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BanTarget {
+    Ip(IpRange),
+
+    Account(Uuid),
+}
+
+/// A single address or a CIDR range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange(pub IpNet);
+
+//
+
+/// Longer temporary bans are cut to this
+pub const MAX_BAN: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+//
+
+impl Bans {
+    pub fn new(list: Vec<Ban>) -> Self {
+        Self {
+            list: RwLock::new(list),
+        }
+    }
+
+    /// The ban that blocks this client, if any
+    pub fn find(&self, ip: IpAddr, account_id: Option<Uuid>) -> Option<Ban> {
+        let now = Utc::now();
+        self.list
+            .read()
+            .unwrap()
+            .iter()
+            .find(|ban| !ban.expired(now) && ban.target.matches(ip, account_id))
+            .cloned()
+    }
+
+    /// Replaces an older ban of the same target
+    pub fn add(&self, ban: Ban) {
+        let mut list = self.list.write().unwrap();
+        list.retain(|old| old.target != ban.target);
+        list.push(ban);
+    }
+
+    /// Active bans, expired bans are dropped
+    pub fn list(&self) -> Vec<Ban> {
+        let now = Utc::now();
+        let mut list = self.list.write().unwrap();
+        list.retain(|ban| !ban.expired(now));
+        list.clone()
+    }
+
+    pub fn replace(&self, list: Vec<Ban>) {
+        *self.list.write().unwrap() = list;
+    }
+}
+
+impl Ban {
+    /// When a ban of `duration` from now expires, `None` for permanent bans
+    pub fn expires_in(duration: Option<Duration>) -> Option<DateTime<Utc>> {
+        let duration = chrono::Duration::from_std(duration?.min(MAX_BAN))
+            .expect("MAX_BAN fits in a chrono::Duration");
+        Some(Utc::now() + duration)
+    }
+
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    pub fn fail_reason(&self) -> ServerInitFailReason {
+        ServerInitFailReason::Banned {
+            reason: self.reason.clone(),
+            expires: self.expires.map(Into::into),
+        }
+    }
+}
+
+impl BanTarget {
+    pub fn matches(&self, ip: IpAddr, account_id: Option<Uuid>) -> bool {
+        match self {
+            // IPv4 clients of a dual stack listener show up as `::ffff:a.b.c.d`
+            BanTarget::Ip(range) => range.0.contains(&ip.to_canonical()),
+            BanTarget::Account(id) => account_id == Some(*id),
+        }
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(ip: IpAddr) -> Self {
+        Self(ip.to_canonical().into())
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = IpAddr::from_str(s) {
+            return Ok(ip.into());
+        }
+        IpNet::from_str(s)
+            .map(|net| Self(net.trunc()))
+            .map_err(|_| format!("`{s}` is not an IP address or a CIDR range"))
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // single addresses without the prefix
+        if self.0.prefix_len() == self.0.max_prefix_len() {
+            write!(f, "{}", self.0.addr())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BanTarget::Ip(range) => write!(f, "ip {range}"),
+            BanTarget::Account(id) => write!(f, "account {id}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn ban(target: &str, expires: Option<DateTime<Utc>>) -> Ban {
+        Ban {
+            target: BanTarget::Ip(target.parse().unwrap()),
+            reason: "spam".to_string(),
+            expires,
+        }
+    }
+
+    #[test]
+    fn parse_ip_ranges() {
+        assert_eq!(
+            "10.0.0.1".parse::<IpRange>().unwrap().to_string(),
+            "10.0.0.1"
+        );
+        assert_eq!(
+            "10.1.2.3/8".parse::<IpRange>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            "2001:db8::1".parse::<IpRange>().unwrap().to_string(),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            "2001:db8:1:2::5/48".parse::<IpRange>().unwrap().to_string(),
+            "2001:db8:1::/48"
+        );
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn ip_ranges_match() {
+        let v4 = BanTarget::Ip("10.0.0.0/8".parse().unwrap());
+        assert!(v4.matches(ip("10.200.0.1"), None));
+        assert!(!v4.matches(ip("11.0.0.1"), None));
+        // IPv4 clients of a dual stack listener
+        assert!(v4.matches(ip("::ffff:10.0.0.1"), None));
+
+        let v6 = BanTarget::Ip("2001:db8::/32".parse().unwrap());
+        assert!(v6.matches(ip("2001:db8:ffff::1"), None));
+        assert!(!v6.matches(ip("2001:db9::1"), None));
+        assert!(!v6.matches(ip("10.0.0.1"), None));
+
+        let single = BanTarget::Ip(IpRange::from(ip("::ffff:10.0.0.1")));
+        assert!(single.matches(ip("10.0.0.1"), None));
+        assert!(!single.matches(ip("10.0.0.2"), None));
+    }
+
+    #[test]
+    fn account_bans_match_logged_in_members() {
+        let account = Uuid::new_v4();
+        let target = BanTarget::Account(account);
+        assert!(target.matches(ip("10.0.0.1"), Some(account)));
+        assert!(!target.matches(ip("10.0.0.1"), Some(Uuid::new_v4())));
+        assert!(!target.matches(ip("10.0.0.1"), None));
+    }
+
+    #[test]
+    fn expired_bans_are_ignored_and_dropped() {
+        let now = Utc::now();
+        let bans = Bans::new(vec![
+            ban("10.0.0.1", Some(now - Duration::minutes(1))),
+            ban("10.0.0.2", Some(now + Duration::minutes(1))),
+            ban("10.0.0.3", None),
+        ]);
+
+        assert!(bans.find(ip("10.0.0.1"), None).is_none());
+        assert!(bans.find(ip("10.0.0.2"), None).is_some());
+        assert!(bans.find(ip("10.0.0.3"), None).is_some());
+        assert_eq!(bans.list().len(), 2);
+    }
+
+    #[test]
+    fn long_bans_are_cut() {
+        let expires = Ban::expires_in(Some(std::time::Duration::MAX)).unwrap();
+        assert!(expires <= Utc::now() + chrono::Duration::from_std(MAX_BAN).unwrap());
+        assert_eq!(Ban::expires_in(None), None);
+    }
+
+    #[test]
+    fn add_replaces_the_same_target() {
+        let bans = Bans::default();
+        bans.add(ban("10.0.0.1", None));
+        bans.add(Ban {
+            reason: "again".to_string(),
+            ..ban("10.0.0.1", None)
+        });
+
+        let list = bans.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].reason, "again");
+    }
+}
@@ -0,0 +1,657 @@
+use crate::DEFAULT_ADDRESS;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+use tui_chat_app_common::{
+    compat::VersionMismatchPolicy, role::Role, MAX_MEMBERS, MAX_MESSAGE_LENGTH, MAX_PACKET_BYTES,
+};
+use uuid::Uuid;
+
+//
+
+// config file and the cli flags that override it
+#[derive(Debug, Clone, Args)]
+pub struct ConfigArgs {
+    /// Server config file (TOML)
+    #[clap(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Server listen address (IPv4 or IPv6)
+    ///
+    /// Note: you might have to surround IPv6 addresses with '' or ""
+    ///
+    /// Examples:
+    /// - `127.0.0.1:1234`
+    /// - `[::1]:1234`
+    /// - `0.0.0.0:1234`
+    /// - `[::]:1234`
+    ///
+    /// Overrides `server.listen` [default: `[::]:13331`]
+    #[clap(short, long, value_name = "ADDRESS")]
+    pub listen: Option<SocketAddr>,
+
+    /// Server name
+    ///
+    /// Overrides `server.name`
+    #[clap(long)]
+    pub name: Option<String>,
+
+    /// Message of the day
+    ///
+    /// Overrides `server.motd`
+    #[clap(long)]
+    pub motd: Option<String>,
+
+    /// Directory for the server data
+    ///
+    /// Overrides `persistence.dir`
+    #[clap(long, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+}
+
+/// Server configuration file (TOML)
+///
+/// Every field is optional, missing
+/// fields use the default values.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub limits: LimitsConfig,
+    pub version: VersionConfig,
+    pub rate_limit: RateLimitConfig,
+    pub persistence: PersistenceConfig,
+    pub moderation: ModerationConfig,
+    pub shutdown: ShutdownConfig,
+    pub metrics: MetricsConfig,
+    pub admin: AdminConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Server listen address (IPv4 or IPv6)
+    pub listen: SocketAddr,
+
+    pub name: String,
+
+    pub description: String,
+
+    /// Message of the day
+    pub motd: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum number of members connected at once
+    pub max_members: usize,
+
+    /// Maximum number of characters in a message
+    pub max_message_length: usize,
+
+    /// Number of latest messages sent to new members
+    pub history_size: usize,
+
+    /// Connections allowed from one IP address, 0 for no limit
+    pub max_connections_per_ip: usize,
+
+    /// Connections allowed from one IPv6 subnet, 0 for no limit
+    pub max_connections_per_subnet: usize,
+
+    /// Prefix length of the IPv6 subnets,
+    /// one home or server usually gets a /64
+    pub ipv6_subnet_prefix: u8,
+
+    /// Packets queued for every member before slow members lag behind
+    ///
+    /// Changing this requires a restart
+    pub broadcast_buffer: usize,
+
+    /// What to do with a member that lagged behind
+    ///
+    /// `resync` or `disconnect`
+    pub lag_policy: LagPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LagPolicy {
+    /// Send the members and the history again
+    #[default]
+    Resync,
+
+    /// Disconnect the member
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersionConfig {
+    /// Which client versions are allowed to connect
+    ///
+    /// `same-major`, `same-minor` or `same`
+    pub policy: VersionMismatchPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Messages per second a member can send on average
+    pub message_rate: f32,
+
+    /// Messages a member can send in a quick burst
+    pub message_burst: u32,
+
+    /// Other requests (member list, history, ...)
+    /// per second a member can send on average
+    pub request_rate: f32,
+
+    /// Other requests a member can send in a quick burst
+    pub request_burst: u32,
+
+    /// Seconds a member has to wait between messages
+    /// in the chat channel, 0 turns slow mode off
+    ///
+    /// Owners can change it live with `/slowmode`,
+    /// a reload only applies it if this value changed.
+    /// Admins are not affected
+    pub slow_mode_secs: u64,
+
+    /// Warnings for hitting the rate limits before a member is muted
+    pub flood_warnings: u32,
+
+    /// Seconds a flooding member is muted for
+    pub flood_mute_secs: u64,
+
+    /// Flood mutes before a member is kicked, 0 never kicks
+    pub flood_mutes_before_kick: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// Directory for the server data (message history, bans, ...)
+    ///
+    /// Nothing is saved if this is not set
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// Messages containing any of these words are rejected
+    ///
+    /// Case insensitive
+    pub blocked_words: Vec<String>,
+
+    /// Account ids of the server owners
+    ///
+    /// `tui-chat-app-client --account-id` prints it
+    pub owners: Vec<Uuid>,
+
+    /// Account ids of the server admins
+    pub admins: Vec<Uuid>,
+
+    /// Account ids of the moderators
+    pub moderators: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Seconds to wait for the connections to close
+    /// after every member was told about the shutdown
+    pub timeout_secs: u64,
+
+    /// Seconds until the server is expected to be back,
+    /// told to the members when it shuts down
+    ///
+    /// Set it and reload the config before a planned restart
+    pub restart_eta_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serve Prometheus metrics over HTTP at `/metrics`
+    ///
+    /// Anyone who can connect can read them,
+    /// listen on a local address like `127.0.0.1:9331`
+    pub listen: Option<SocketAddr>,
+
+    /// Write the metrics to this file every `interval_secs`
+    pub file: Option<PathBuf>,
+
+    /// Seconds between metric file writes,
+    /// also the window for the per second rates
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Unix socket for admin commands, only the server user can connect
+    ///
+    /// Try `echo help | nc -U <socket>`
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("Invalid config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("Invalid config value `{field}`: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+//
+
+/// Clients send a few requests after connecting
+/// and keep sending typing packets and read markers
+const MIN_REQUEST_RATE: f32 = 1.0;
+const MIN_REQUEST_BURST: u32 = 5;
+
+//
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_ADDRESS,
+            name: "tui-chat-app server".to_string(),
+            description: String::new(),
+            motd: String::new(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_members: 256,
+            max_message_length: MAX_MESSAGE_LENGTH,
+            history_size: 100,
+            max_connections_per_ip: 4,
+            max_connections_per_subnet: 16,
+            ipv6_subnet_prefix: 64,
+            broadcast_buffer: 256,
+            lag_policy: LagPolicy::default(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            message_rate: 1.0,
+            message_burst: 5,
+            request_rate: 5.0,
+            request_burst: 20,
+            slow_mode_secs: 0,
+            flood_warnings: 3,
+            flood_mute_secs: 60,
+            flood_mutes_before_kick: 2,
+        }
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 5,
+            restart_eta_secs: None,
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            file: None,
+            interval_secs: 15,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// `None` if slow mode is off
+    pub fn slow_mode(&self) -> Option<Duration> {
+        (self.slow_mode_secs != 0).then(|| Duration::from_secs(self.slow_mode_secs))
+    }
+
+    pub fn flood_mute(&self) -> Duration {
+        Duration::from_secs(self.flood_mute_secs)
+    }
+}
+
+impl ConfigArgs {
+    /// Read the config file (if any), apply
+    /// the cli overrides and validate the result
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(listen) = self.listen {
+            config.server.listen = listen;
+        }
+        if let Some(name) = &self.name {
+            config.server.name = name.clone();
+        }
+        if let Some(motd) = &self.motd {
+            config.server.motd = motd.clone();
+        }
+        if let Some(dir) = &self.data_dir {
+            config.persistence.dir = Some(dir.clone());
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Clients without an account are guests
+    pub fn role(&self, account_id: Option<Uuid>) -> Role {
+        let Some(id) = account_id else {
+            return Role::Guest;
+        };
+        let moderation = &self.moderation;
+        if moderation.owners.contains(&id) {
+            Role::Owner
+        } else if moderation.admins.contains(&id) {
+            Role::Admin
+        } else if moderation.moderators.contains(&id) {
+            Role::Moderator
+        } else {
+            Role::Member
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let file = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        toml::from_str(&file).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(field: &'static str, reason: impl Into<String>) -> ConfigError {
+            ConfigError::Invalid {
+                field,
+                reason: reason.into(),
+            }
+        }
+
+        if self.server.name.trim().is_empty() {
+            return Err(invalid("server.name", "cannot be empty"));
+        }
+        if self.server.description.chars().any(char::is_control) {
+            return Err(invalid(
+                "server.description",
+                "cannot contain control characters",
+            ));
+        }
+        if self
+            .server
+            .motd
+            .chars()
+            .any(|c| c.is_control() && c != '\n')
+        {
+            return Err(invalid(
+                "server.motd",
+                "cannot contain control characters other than newlines",
+            ));
+        }
+
+        let limits = &self.limits;
+        if !(1..=MAX_MEMBERS).contains(&limits.max_members) {
+            return Err(invalid(
+                "limits.max_members",
+                format!("has to be between 1 and {MAX_MEMBERS}"),
+            ));
+        }
+        if limits.max_message_length == 0 {
+            return Err(invalid("limits.max_message_length", "cannot be 0"));
+        }
+        // the whole history has to fit in one packet,
+        // a char is at most 4 bytes + some room for the ids and the timestamp
+        let history_bytes = limits
+            .history_size
+            .saturating_mul(limits.max_message_length.saturating_mul(4) + 64);
+        if history_bytes > MAX_PACKET_BYTES {
+            return Err(invalid(
+                "limits.history_size",
+                format!(
+                    "history of {} messages of {} characters doesn't fit in a packet",
+                    limits.history_size, limits.max_message_length
+                ),
+            ));
+        }
+
+        if limits.broadcast_buffer == 0 {
+            return Err(invalid("limits.broadcast_buffer", "cannot be 0"));
+        }
+        if limits.ipv6_subnet_prefix > 128 {
+            return Err(invalid(
+                "limits.ipv6_subnet_prefix",
+                "has to be between 0 and 128",
+            ));
+        }
+
+        let rate_limit = &self.rate_limit;
+        if !(rate_limit.message_rate.is_finite() && rate_limit.message_rate > 0.0) {
+            return Err(invalid(
+                "rate_limit.message_rate",
+                "has to be a positive number",
+            ));
+        }
+        if rate_limit.message_burst == 0 {
+            return Err(invalid("rate_limit.message_burst", "cannot be 0"));
+        }
+        if !(rate_limit.request_rate.is_finite() && rate_limit.request_rate >= MIN_REQUEST_RATE) {
+            return Err(invalid(
+                "rate_limit.request_rate",
+                format!("has to be at least {MIN_REQUEST_RATE}"),
+            ));
+        }
+        if rate_limit.request_burst < MIN_REQUEST_BURST {
+            return Err(invalid(
+                "rate_limit.request_burst",
+                format!("has to be at least {MIN_REQUEST_BURST}"),
+            ));
+        }
+
+        if let Some(dir) = &self.persistence.dir {
+            if dir.exists() && !dir.is_dir() {
+                return Err(invalid(
+                    "persistence.dir",
+                    format!("{} is not a directory", dir.display()),
+                ));
+            }
+        }
+
+        if self
+            .moderation
+            .blocked_words
+            .iter()
+            .any(|word| word.trim().is_empty())
+        {
+            return Err(invalid(
+                "moderation.blocked_words",
+                "cannot contain empty words",
+            ));
+        }
+
+        if self.metrics.interval_secs == 0 {
+            return Err(invalid("metrics.interval_secs", "cannot be 0"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn args(config: Option<PathBuf>) -> ConfigArgs {
+        ConfigArgs {
+            config,
+            listen: None,
+            name: None,
+            motd: None,
+            data_dir: None,
+        }
+    }
+
+    /// Load `file` like the server does
+    fn load(file: &str) -> Result<Config, ConfigError> {
+        load_with(file, |_| {})
+    }
+
+    fn load_with(file: &str, cli: impl FnOnce(&mut ConfigArgs)) -> Result<Config, ConfigError> {
+        let dir = TempDir::new();
+        let path = dir.join("config.toml");
+        fs::write(&path, file).unwrap();
+
+        let mut args = args(Some(path));
+        cli(&mut args);
+        args.load()
+    }
+
+    fn invalid_field(result: Result<Config, ConfigError>) -> &'static str {
+        match result {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("expected an invalid value, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = args(None).load().unwrap();
+        assert_eq!(config.server.listen, DEFAULT_ADDRESS);
+        assert!(load("").is_ok());
+    }
+
+    #[test]
+    fn written_config_loads_again() {
+        // like the setup writes it
+        let mut config = Config::default();
+        config.server.name = "test server".to_string();
+        config.persistence.dir = Some("/var/lib/tui-chat-app".into());
+        let file = toml::to_string_pretty(&config).unwrap();
+
+        let loaded = load(&file).unwrap();
+        assert_eq!(loaded.server.name, "test server");
+        assert_eq!(loaded.persistence.dir, config.persistence.dir);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let cases = [
+            (
+                "[rate_limit]\nmessage_rate = 0.0",
+                "rate_limit.message_rate",
+            ),
+            (
+                "[rate_limit]\nmessage_rate = -1.0",
+                "rate_limit.message_rate",
+            ),
+            (
+                "[rate_limit]\nmessage_burst = 0",
+                "rate_limit.message_burst",
+            ),
+            (
+                "[rate_limit]\nrequest_rate = 0.5",
+                "rate_limit.request_rate",
+            ),
+            ("[server]\nname = \"  \"", "server.name"),
+            ("[limits]\nmax_members = 0", "limits.max_members"),
+            ("[limits]\nbroadcast_buffer = 0", "limits.broadcast_buffer"),
+            (
+                "[limits]\nipv6_subnet_prefix = 129",
+                "limits.ipv6_subnet_prefix",
+            ),
+            ("[metrics]\ninterval_secs = 0", "metrics.interval_secs"),
+            (
+                "[moderation]\nblocked_words = [\"spam\", \" \"]",
+                "moderation.blocked_words",
+            ),
+        ];
+        for (file, field) in cases {
+            assert_eq!(invalid_field(load(file)), field, "{file}");
+        }
+    }
+
+    #[test]
+    fn unparseable_values_are_rejected() {
+        for file in [
+            "[server]\nlisten = \"\"",
+            "[server]\nlisten = \"localhost\"",
+            "[version]\npolicy = \"any\"",
+            "[limits]\nlag_policy = \"drop\"",
+            "[server]\nunknown = 1",
+        ] {
+            assert!(
+                matches!(load(file), Err(ConfigError::Parse { .. })),
+                "{file}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_overrides_the_file() {
+        let file = r#"
+            [server]
+            listen = "127.0.0.1:1000"
+            name = "from the file"
+            motd = "file motd"
+
+            [persistence]
+            dir = "/from/the/file"
+        "#;
+
+        let config = load(file).unwrap();
+        assert_eq!(config.server.name, "from the file");
+
+        let config = load_with(file, |args| {
+            args.listen = Some("127.0.0.1:2000".parse().unwrap());
+            args.name = Some("from the cli".to_string());
+            args.motd = Some("cli motd".to_string());
+            args.data_dir = Some("/from/the/cli".into());
+        })
+        .unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:2000".parse().unwrap());
+        assert_eq!(config.server.name, "from the cli");
+        assert_eq!(config.server.motd, "cli motd");
+        assert_eq!(config.persistence.dir, Some("/from/the/cli".into()));
+    }
+
+    #[test]
+    fn cli_overrides_are_validated() {
+        let result = load_with("", |args| args.name = Some(String::new()));
+        assert_eq!(invalid_field(result), "server.name");
+    }
+}
@@ -0,0 +1,204 @@
+use crate::config::LimitsConfig;
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr},
+};
+use tui_chat_app_common::server::ServerInitFailReason;
+
+//
+
+/// Open connections per address and per IPv6 subnet
+#[derive(Debug, Default)]
+pub struct Connections {
+    ips: DashMap<IpAddr, usize>,
+    subnets: DashMap<Ipv6Addr, usize>,
+}
+
+/// A counted connection, give it back with [`Connections::remove`]
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    subnet: Option<Ipv6Addr>,
+}
+
+//
+
+impl Connections {
+    /// Count a new connection, fails if it would go over the limits
+    pub fn add(
+        &self,
+        ip: IpAddr,
+        limits: &LimitsConfig,
+    ) -> Result<ConnectionSlot, ServerInitFailReason> {
+        // IPv4 clients of a dual stack listener show up as `::ffff:a.b.c.d`
+        let ip = ip.to_canonical();
+        let subnet = match ip {
+            IpAddr::V6(ip) => Some(subnet(ip, limits.ipv6_subnet_prefix)),
+            IpAddr::V4(_) => None,
+        };
+
+        // the ip entry is locked until the subnet is checked too
+        let mut ip_count = self.ips.entry(ip).or_default();
+        if over_limit(*ip_count, limits.max_connections_per_ip) {
+            return Err(match limits.max_connections_per_ip {
+                1 => ServerInitFailReason::AlreadyConnected,
+                limit => ServerInitFailReason::TooManyConnections { limit },
+            });
+        }
+
+        if let Some(subnet) = subnet {
+            let mut subnet_count = self.subnets.entry(subnet).or_default();
+            if over_limit(*subnet_count, limits.max_connections_per_subnet) {
+                // don't leave an empty entry behind,
+                // unlock in order to not deadlock with `add`
+                drop(subnet_count);
+                drop(ip_count);
+                self.ips.remove_if(&ip, |_, count| *count == 0);
+                return Err(ServerInitFailReason::TooManyConnections {
+                    limit: limits.max_connections_per_subnet,
+                });
+            }
+            *subnet_count += 1;
+        }
+        *ip_count += 1;
+
+        Ok(ConnectionSlot { ip, subnet })
+    }
+
+    /// No connection is open
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty()
+    }
+
+    pub fn remove(&self, slot: ConnectionSlot) {
+        release(&self.ips, slot.ip);
+        if let Some(subnet) = slot.subnet {
+            release(&self.subnets, subnet);
+        }
+    }
+}
+
+/// 0 is no limit
+fn over_limit(count: usize, limit: usize) -> bool {
+    limit != 0 && count >= limit
+}
+
+fn release<K: Eq + Hash>(counts: &DashMap<K, usize>, key: K) {
+    if let Entry::Occupied(mut entry) = counts.entry(key) {
+        *entry.get_mut() -= 1;
+        if *entry.get() == 0 {
+            entry.remove();
+        }
+    }
+}
+
+/// The network address of `ip` with a `prefix` bit long mask
+fn subnet(ip: Ipv6Addr, prefix: u8) -> Ipv6Addr {
+    let mask = u128::MAX
+        .checked_shl(128 - prefix.min(128) as u32)
+        .unwrap_or(0);
+    Ipv6Addr::from(u128::from(ip) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(per_ip: usize, per_subnet: usize) -> LimitsConfig {
+        LimitsConfig {
+            max_connections_per_ip: per_ip,
+            max_connections_per_subnet: per_subnet,
+            ..LimitsConfig::default()
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn subnet_masks_the_host_bits() {
+        let ip: Ipv6Addr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+
+        assert_eq!(
+            subnet(ip, 64),
+            "2001:db8:1:2::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(subnet(ip, 48), "2001:db8:1::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(subnet(ip, 56), "2001:db8:1::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(subnet(ip, 128), ip);
+        assert_eq!(subnet(ip, 0), Ipv6Addr::UNSPECIFIED);
+        // out of range prefixes are clamped
+        assert_eq!(subnet(ip, 200), ip);
+    }
+
+    #[test]
+    fn per_ip_limit() {
+        let connections = Connections::default();
+        let limits = limits(2, 0);
+
+        let a = connections.add(ip("10.0.0.1"), &limits).unwrap();
+        let _b = connections.add(ip("10.0.0.1"), &limits).unwrap();
+        assert!(matches!(
+            connections.add(ip("10.0.0.1"), &limits),
+            Err(ServerInitFailReason::TooManyConnections { limit: 2 })
+        ));
+        // other addresses have their own count
+        let _c = connections.add(ip("10.0.0.2"), &limits).unwrap();
+
+        connections.remove(a);
+        connections.add(ip("10.0.0.1"), &limits).unwrap();
+    }
+
+    #[test]
+    fn one_connection_per_ip_is_already_connected() {
+        let connections = Connections::default();
+        let limits = limits(1, 0);
+
+        let _a = connections.add(ip("10.0.0.1"), &limits).unwrap();
+        assert!(matches!(
+            connections.add(ip("10.0.0.1"), &limits),
+            Err(ServerInitFailReason::AlreadyConnected)
+        ));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_count_as_ipv4() {
+        let connections = Connections::default();
+        let limits = limits(1, 0);
+
+        let _a = connections.add(ip("10.0.0.1"), &limits).unwrap();
+        assert!(connections.add(ip("::ffff:10.0.0.1"), &limits).is_err());
+    }
+
+    #[test]
+    fn per_subnet_limit() {
+        let connections = Connections::default();
+        let limits = limits(0, 2);
+
+        let a = connections.add(ip("2001:db8::1"), &limits).unwrap();
+        let _b = connections.add(ip("2001:db8::2"), &limits).unwrap();
+        assert!(matches!(
+            connections.add(ip("2001:db8::3"), &limits),
+            Err(ServerInitFailReason::TooManyConnections { limit: 2 })
+        ));
+        // another /64
+        let _c = connections.add(ip("2001:db8:0:1::1"), &limits).unwrap();
+
+        connections.remove(a);
+        connections.add(ip("2001:db8::3"), &limits).unwrap();
+    }
+
+    #[test]
+    fn refused_connections_are_not_counted() {
+        let connections = Connections::default();
+        let limits = limits(0, 1);
+
+        let a = connections.add(ip("2001:db8::1"), &limits).unwrap();
+        assert!(connections.add(ip("2001:db8::2"), &limits).is_err());
+
+        connections.remove(a);
+        assert!(connections.is_empty());
+    }
+}
@@ -1,139 +1,496 @@
-use dashmap::DashSet;
+use crate::{
+    config::LagPolicy,
+    message_senders::MessageSender,
+    rate_limit::{Flood, Penalty, TokenBucket},
+    state::{Control, Member, Shared},
+    validate,
+};
 use eznet::{packet::Packet, socket::Socket};
-use std::{net::IpAddr, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 use tokio::{
-    sync::broadcast::{Receiver, Sender},
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+    },
     time::Instant,
 };
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
 use tui_chat_app_common::{
-    client::{ClientChatPacket, ClientInitPacket, ClientPacket},
+    client::{ClientChatPacket, ClientInitPacket, ClientPacket, ModerationAction},
     compat::COMPAT_INFO,
-    server::{ServerChatPacket, ServerInitFailReason, ServerInitPacket, ServerPacket},
+    role::{Permission, Role},
+    server::{
+        AuditAction, DisconnectReason, HistoryMessage, InvalidStateReason, RejectReason,
+        ServerChatPacket, ServerInitFailReason, ServerInitPacket, ServerPacket, WarningReason,
+    },
+    trace::Direction,
     FromPacketBytes, IntoPacketBytes,
 };
 use uuid::Uuid;
 
 //
 
-pub async fn handler(
-    mut socket: Socket,
-    connections: Arc<DashSet<IpAddr>>,
-    send: Arc<Sender<ServerPacket>>,
-    recv: Receiver<ServerPacket>,
-) {
-    if false && !connections.insert(socket.remote().ip()) {
-        // already connected from this ip
-        let _ = socket
-            .send(Packet::ordered(
-                ServerInitPacket::Fail {
-                    reason: ServerInitFailReason::AlreadyConnected,
-                }
-                .into_bytes(),
-                None,
-            ))
-            .await;
-        return;
-    }
+/// Per connection state
+struct Client {
+    id: Uuid,
+    message_limit: TokenBucket,
+    /// Every request other than messages
+    request_limit: TokenBucket,
+    /// For the slow mode
+    last_message: Option<Instant>,
+    flood: Flood,
+    /// When to resync a client that lagged behind
+    lag_resync: Option<Instant>,
+    last_typing: Option<Instant>,
+}
+
+/// Why the chat state ended
+enum ChatExit {
+    /// The client has to redo the handshake
+    Resync(InvalidStateReason),
+
+    /// Disconnected by the server
+    Disconnect(DisconnectReason),
+}
+
+//
+
+/// Time for the [`ServerChatPacket::Disconnect`]
+/// to reach the client before the socket is closed
+const DISCONNECT_GRACE: Duration = Duration::from_millis(500);
 
-    println!("New connection from {}", socket.remote());
+/// Lagging more within this time only resyncs once
+const LAG_RESYNC_DELAY: Duration = Duration::from_millis(250);
 
-    if handler_try(&mut socket, send, recv).await.is_none() {
-        eprintln!("Client error");
+/// Typing notices more often than this aren't broadcast
+const TYPING_INTERVAL: Duration = Duration::SECOND;
+
+/// Time for a refused client to send its info,
+/// so that it can be told why it was refused
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Ids for telling connections apart in the logs
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+//
+
+pub async fn handler(socket: Socket, shared: Arc<Shared>) {
+    let span = info_span!(
+        "connection",
+        id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+        addr = %socket.remote(),
+        member = field::Empty,
+    );
+    connection(socket, shared).instrument(span).await
+}
+
+async fn connection(mut socket: Socket, shared: Arc<Shared>) {
+    info!("New connection");
+
+    let limits = &shared.config().limits;
+    let slot = match shared.connections.add(socket.remote().ip(), limits) {
+        Ok(slot) => slot,
+        Err(reason) => {
+            refuse(&mut socket, &shared, reason).await;
+            close(socket).await;
+            return;
+        }
+    };
+
+    let limits = shared.config().rate_limit.clone();
+    let mut client = Client {
+        id: Uuid::new_v4(),
+        message_limit: TokenBucket::new(limits.message_burst),
+        request_limit: TokenBucket::new(limits.request_burst),
+        last_message: None,
+        flood: Flood::default(),
+        lag_resync: None,
+        last_typing: None,
+    };
+    let recv = shared.broadcast.subscribe();
+    let control = mpsc::unbounded_channel();
+    let result = handler_try(&mut socket, &shared, recv, control, &mut client).await;
+
+    if shared.members.remove(&client.id).is_some() {
+        let _ = shared.broadcast.send(
+            ServerChatPacket::RemoveMember {
+                member_id: client.id,
+                reason: result.clone(),
+            }
+            .into(),
+        );
+    }
+
+    match result {
+        Some(reason) => info!(%reason, "Disconnected"),
+        None => info!(reason = "connection closed", "Disconnected"),
     }
 
-    println!("Disconnected {}", socket.remote());
+    shared.connections.remove(slot);
+    close(socket).await;
+}
+
+/// Answer the handshake with a failure
+async fn refuse(socket: &mut Socket, shared: &Shared, reason: ServerInitFailReason) {
+    warn!(%reason, "Connection refused");
+    shared.stats.handshake_failed(&reason);
 
-    connections.remove(&socket.remote().ip());
+    // the client only reads the response after sending its info,
+    // don't let a silent one hold on to the connection
+    let info = tokio::time::timeout(REFUSE_TIMEOUT, recv_packet(socket, shared)).await;
+    if let Ok(Some(_)) = info {
+        let _ = send_packet(socket, shared, ServerInitPacket::Fail { reason }).await;
+    }
+}
+
+async fn close(socket: Socket) {
+    // dropping a live eznet socket blocks
+    // until its workers stop, keep that
+    // off of the runtime workers
+    let _ = tokio::task::spawn_blocking(move || drop(socket)).await;
 }
 
+/// `Some` if the server disconnected the client
 async fn handler_try(
     socket: &mut Socket,
-    send: Arc<Sender<ServerPacket>>,
+    shared: &Shared,
     mut recv: Receiver<ServerPacket>,
-) -> Option<()> {
-    // Init state
+    (control_send, mut control): (UnboundedSender<Control>, UnboundedReceiver<Control>),
+    client: &mut Client,
+) -> Option<DisconnectReason> {
+    let account_id = init_state(socket, shared, false).await?;
+
+    shared.members.insert(
+        client.id,
+        Member::new(socket.remote(), account_id, control_send),
+    );
+    Span::current().record("member", field::display(client.id));
+    info!(account = account_id.map(field::display), "Joined");
+    let _ = shared.broadcast.send(
+        ServerChatPacket::NewMember {
+            member_id: client.id,
+        }
+        .into(),
+    );
+    broadcast_role(shared, client.id);
 
-    let packet = recv_packet(socket).await?;
-    let response = match init_state(packet) {
-        Ok(()) => ServerInitPacket::Success(COMPAT_INFO),
-        Err(reason) => ServerInitPacket::Fail { reason },
+    loop {
+        let reason = match chat_state(socket, shared, &mut recv, &mut control, client).await? {
+            ChatExit::Resync(reason) => reason,
+            ChatExit::Disconnect(reason) => return Some(reason),
+        };
+
+        // back to the init state,
+        // the client should redo the handshake
+        info!(%reason, "Resyncing");
+        send_packet(socket, shared, ServerChatPacket::InvalidState { reason }).await?;
+        let account_id = init_state(socket, shared, true).await?;
+
+        // the client might have logged in with another account
+        if let Some(mut member) = shared.members.get_mut(&client.id) {
+            member.account_id = account_id;
+        }
+        broadcast_role(shared, client.id);
+    }
+}
+
+fn broadcast_role(shared: &Shared, member_id: Uuid) {
+    let roles = [(member_id, shared.role(member_id))].into();
+    let _ = shared
+        .broadcast
+        .send(ServerChatPacket::MemberRoles { roles }.into());
+}
+
+/// Returns the account id if the client logged in
+///
+/// `resync` skips chat packets that
+/// were sent before the client noticed
+/// the [`ServerChatPacket::InvalidState`]
+async fn init_state(socket: &mut Socket, shared: &Shared, resync: bool) -> Option<Option<Uuid>> {
+    let packet = loop {
+        match recv_packet(socket, shared).await? {
+            ClientPacket::Chat(_) if resync => continue,
+            packet => break packet,
+        }
     };
 
-    socket
-        .send(Packet::ordered(response.into_bytes(), None))
-        .await?;
+    let result = init_packet(shared, socket.remote(), packet, resync);
+    let account_id = result.as_ref().ok().copied();
+    let response = match result {
+        Ok(_) => ServerInitPacket::Success(COMPAT_INFO),
+        Err(reason) => {
+            warn!(%reason, "Handshake failed");
+            shared.stats.handshake_failed(&reason);
+            ServerInitPacket::Fail { reason }
+        }
+    };
 
-    // Chat state
+    send_packet(socket, shared, response).await?;
+    let account_id = account_id?;
 
-    let client = Uuid::new_v4();
+    send_packet(socket, shared, shared.server_info()).await?;
+    Some(account_id)
+}
+
+fn init_packet(
+    shared: &Shared,
+    addr: SocketAddr,
+    packet: ClientPacket,
+    resync: bool,
+) -> Result<Option<Uuid>, ServerInitFailReason> {
+    let (compat, account_id) = match packet {
+        ClientPacket::Init(ClientInitPacket::ClientInfo(i)) => (i, None),
+        ClientPacket::Init(ClientInitPacket::Login { info, token }) => {
+            (info, Some(token.account_id()))
+        }
+        _ => return Err(ServerInitFailReason::InvalidState),
+    };
+
+    if shared.shutting_down() {
+        return Err(ServerInitFailReason::ShuttingDown);
+    }
+
+    if let Some(ban) = shared.bans.find(addr.ip(), account_id) {
+        return Err(ban.fail_reason());
+    }
+
+    let config = shared.config();
+    COMPAT_INFO.compatible(compat, config.version.policy)?;
+
+    // resyncing members are already counted
+    if !resync && shared.members.len() >= config.limits.max_members {
+        return Err(ServerInitFailReason::ServerFull);
+    }
+
+    Ok(account_id)
+}
+
+async fn chat_state(
+    socket: &mut Socket,
+    shared: &Shared,
+    recv: &mut Receiver<ServerPacket>,
+    control: &mut UnboundedReceiver<Control>,
+    client: &mut Client,
+) -> Option<ChatExit> {
     let mut hb = Instant::now() + Duration::SECOND;
 
     loop {
         tokio::select! {
             _ = tokio::time::sleep_until(hb) => {
-                socket.send(Packet::ordered(ClientChatPacket::KeepAlive.into_bytes(), None)).await?;
+                send_packet(socket, shared, ServerChatPacket::KeepAlive).await?;
                 hb = Instant::now() + Duration::SECOND;
             }
-            Some(packet) = recv_packet(socket) => handle_chat_client_recv(socket, send.clone(), packet, client).await?,
-            Ok(packet) = recv.recv() => handle_chat_broadcast(socket, packet).await?,
+            _ = tokio::time::sleep_until(client.lag_resync.unwrap_or(hb)), if client.lag_resync.is_some() => {
+                client.lag_resync = None;
+                resync_lagged(socket, shared).await?;
+            }
+            Some(packet) = recv_packet(socket, shared) => match packet {
+                ClientPacket::Chat(packet) => handle_chat_client_recv(socket, shared, packet, client).await?,
+                _ => return Some(ChatExit::Resync(InvalidStateReason::UnexpectedInit)),
+            },
+            packet = recv.recv() => match packet {
+                Ok(packet) => handle_chat_broadcast(socket, shared, packet).await?,
+                Err(RecvError::Lagged(skipped)) => lagged(shared, client, skipped),
+                Err(RecvError::Closed) => return None,
+            },
+            Some(Control::Disconnect(reason)) = control.recv() => {
+                info!(%reason, "Disconnecting");
+                let packet = ServerChatPacket::Disconnect { reason: reason.clone() };
+                if send_packet(socket, shared, packet).await.is_some() {
+                    // eznet can't flush, give the packet time to go out
+                    tokio::time::sleep(DISCONNECT_GRACE).await;
+                }
+                return Some(ChatExit::Disconnect(reason));
+            }
         }
     }
 }
 
-async fn recv_packet(socket: &mut Socket) -> Option<ClientPacket> {
-    ClientPacket::from_bytes(socket.recv().await?.bytes)
-}
-
-fn init_state(packet: ClientPacket) -> Result<(), ServerInitFailReason> {
-    let compat = match packet {
-        ClientPacket::Init(ClientInitPacket::ClientInfo(i)) => i,
-        _ => return Err(ServerInitFailReason::InvalidState),
+async fn recv_packet(socket: &mut Socket, shared: &Shared) -> Option<ClientPacket> {
+    let bytes = socket.recv().await?.bytes;
+    shared.stats.packet_in(bytes.len());
+    let Some(packet) = ClientPacket::from_bytes(bytes) else {
+        warn!("Invalid packet");
+        return None;
     };
+    shared.trace.log(Direction::Recv, socket.remote(), &packet);
+    Some(packet)
+}
 
-    COMPAT_INFO.compatible(compat)?;
-
-    Ok(())
+async fn send_packet(
+    socket: &Socket,
+    shared: &Shared,
+    packet: impl Into<ServerPacket>,
+) -> Option<()> {
+    let packet = packet.into();
+    shared.trace.log(Direction::Send, socket.remote(), &packet);
+    let bytes = packet.into_bytes();
+    shared.stats.packet_out(bytes.len());
+    socket.send(Packet::ordered(bytes, None)).await
 }
 
 async fn handle_chat_client_recv(
     socket: &mut Socket,
-    send: Arc<Sender<ServerPacket>>,
-    packet: ClientPacket,
-    client: Uuid,
+    shared: &Shared,
+    packet: ClientChatPacket,
+    client: &mut Client,
 ) -> Option<()> {
-    let packet = match packet {
-        ClientPacket::Chat(p) => p,
-        _ => {
-            socket
-                .send(Packet::ordered(
-                    ServerChatPacket::InvalidState.into_bytes(),
-                    None,
-                ))
-                .await?;
-            return None;
+    // messages have their own limit and keep alives
+    // are sent every second no matter what
+    if !matches!(
+        packet,
+        ClientChatPacket::SendMessage { .. } | ClientChatPacket::KeepAlive
+    ) {
+        let config = shared.config();
+        let limits = &config.rate_limit;
+        if shared.role(client.id) < Role::Admin
+            && client
+                .request_limit
+                .take(limits.request_rate, limits.request_burst)
+                .is_err()
+        {
+            debug!("Request rate limited");
+            return flooding(socket, shared, client).await;
         }
-    };
+    }
 
     match packet {
         ClientChatPacket::SendMessage {
             message_id,
             message,
         } => {
-            send.send(ServerPacket::Chat(ServerChatPacket::NewMessage {
-                sender_id: client,
-                message_id,
-                message: message.trim().to_string(),
-            }))
-            .ok()?;
+            let response = match send_message(shared, client, message_id, &message) {
+                Ok(()) => ServerChatPacket::Ack { message_id },
+                Err(reason) => {
+                    debug!(%message_id, %reason, "Message rejected");
+                    ServerChatPacket::Reject { message_id, reason }
+                }
+            };
+            let rate_limited = matches!(
+                response,
+                ServerChatPacket::Reject {
+                    reason: RejectReason::RateLimited { .. },
+                    ..
+                }
+            );
+            send_packet(socket, shared, response).await?;
+            if rate_limited {
+                flooding(socket, shared, client).await?;
+            }
+        }
+        ClientChatPacket::EditMessage {
+            message_id,
+            message,
+        } => {
+            let response = match edit_message(shared, client, message_id, &message) {
+                Ok(()) => ServerChatPacket::Ack { message_id },
+                Err(reason) => {
+                    debug!(%message_id, %reason, "Edit rejected");
+                    ServerChatPacket::Reject { message_id, reason }
+                }
+            };
+            send_packet(socket, shared, response).await?;
+        }
+        ClientChatPacket::RemoveMessage { message_id } => {
+            let response = match remove_message(shared, client, message_id) {
+                Ok(()) => ServerChatPacket::Ack { message_id },
+                Err(reason) => {
+                    debug!(%message_id, %reason, "Remove rejected");
+                    ServerChatPacket::Reject { message_id, reason }
+                }
+            };
+            send_packet(socket, shared, response).await?;
         }
         ClientChatPacket::RequestSelfMember => {
-            socket
-                .send(Packet::ordered(
-                    ServerPacket::Chat(ServerChatPacket::SelfMember { member_id: client })
-                        .into_bytes(),
-                    None,
-                ))
-                .await?;
+            send_packet(
+                socket,
+                shared,
+                ServerChatPacket::SelfMember {
+                    member_id: client.id,
+                },
+            )
+            .await?;
+        }
+        ClientChatPacket::RequestMembers => {
+            let roles = shared.roles();
+            let member_ids = roles.keys().copied().collect();
+            send_packet(socket, shared, ServerChatPacket::Members { member_ids }).await?;
+            send_packet(socket, shared, ServerChatPacket::MemberRoles { roles }).await?;
+        }
+        ClientChatPacket::RequestHistory => {
+            let messages = shared.history();
+            send_packet(socket, shared, ServerChatPacket::History { messages }).await?;
+
+            let read_marker =
+                account_id(shared, client.id).and_then(|account_id| shared.read_marker(account_id));
+            if let Some(message_id) = read_marker {
+                send_packet(socket, shared, ServerChatPacket::ReadMarker { message_id }).await?;
+            }
+        }
+        ClientChatPacket::Moderate {
+            request_id,
+            member_id,
+            action,
+        } => {
+            let response = match moderate(shared, client, member_id, action) {
+                Ok(()) => ServerChatPacket::Ack {
+                    message_id: request_id,
+                },
+                Err(reason) => {
+                    debug!(%member_id, %reason, "Moderation rejected");
+                    ServerChatPacket::Reject {
+                        message_id: request_id,
+                        reason,
+                    }
+                }
+            };
+            send_packet(socket, shared, response).await?;
+        }
+        ClientChatPacket::RequestAuditLog { request_id } => {
+            let response = match require(shared.role(client.id), Permission::ViewAuditLog) {
+                Ok(()) => ServerChatPacket::AuditLog {
+                    entries: shared.audit_log.recent(),
+                },
+                Err(reason) => ServerChatPacket::Reject {
+                    message_id: request_id,
+                    reason,
+                },
+            };
+            send_packet(socket, shared, response).await?;
+        }
+        ClientChatPacket::SetSlowMode {
+            request_id,
+            slow_mode,
+        } => {
+            let response = match require(shared.role(client.id), Permission::ManageChannels) {
+                Ok(()) => {
+                    shared.set_slow_mode(slow_mode);
+                    ServerChatPacket::Ack {
+                        message_id: request_id,
+                    }
+                }
+                Err(reason) => ServerChatPacket::Reject {
+                    message_id: request_id,
+                    reason,
+                },
+            };
+            send_packet(socket, shared, response).await?;
+        }
+        ClientChatPacket::Typing => typing(shared, client),
+        ClientChatPacket::SetReadMarker { message_id } => {
+            // guests only keep it on their client
+            if let Some(account_id) = account_id(shared, client.id) {
+                if !shared.set_read_marker(account_id, message_id) {
+                    debug!(%message_id, "Read marker ignored");
+                }
+            }
         }
         _ => {}
     }
@@ -141,8 +498,348 @@ async fn handle_chat_client_recv(
     Some(())
 }
 
-async fn handle_chat_broadcast(socket: &mut Socket, packet: ServerPacket) -> Option<()> {
-    socket
-        .send(Packet::ordered(packet.into_bytes(), None))
-        .await
+/// The client missed `skipped` broadcast packets
+fn lagged(shared: &Shared, client: &mut Client, skipped: u64) {
+    shared.stats.lagged(skipped);
+    let policy = shared.config().limits.lag_policy;
+    warn!(skipped, ?policy, "Lagged behind the broadcast");
+
+    match policy {
+        LagPolicy::Resync => {
+            client
+                .lag_resync
+                .get_or_insert_with(|| Instant::now() + LAG_RESYNC_DELAY);
+        }
+        LagPolicy::Disconnect => {
+            // the chat state sends the disconnect packet
+            shared.kick(client.id, DisconnectReason::Lagged);
+        }
+    }
+}
+
+/// Everything the missed broadcast packets could have changed
+async fn resync_lagged(socket: &Socket, shared: &Shared) -> Option<()> {
+    let roles = shared.roles();
+    let member_ids = roles.keys().copied().collect();
+    send_packet(socket, shared, ServerChatPacket::Members { member_ids }).await?;
+    send_packet(socket, shared, ServerChatPacket::MemberRoles { roles }).await?;
+    let messages = shared.history();
+    send_packet(socket, shared, ServerChatPacket::History { messages }).await
+}
+
+/// Escalate after the client hit a rate limit
+async fn flooding(socket: &Socket, shared: &Shared, client: &mut Client) -> Option<()> {
+    let penalty = client.flood.strike(&shared.config().rate_limit);
+    warn!(?penalty, "Flooding");
+
+    let reason = match penalty {
+        Penalty::Warn { warnings_left } => WarningReason::Flooding { warnings_left },
+        Penalty::Mute(duration) => {
+            let action = ModerationAction::Mute {
+                duration: Some(duration),
+            };
+            shared.audit(None, client.id, AuditAction::Moderation(action));
+            shared.mute(client.id, Some(duration));
+            WarningReason::FloodMuted { duration }
+        }
+        Penalty::Kick => {
+            let reason = DisconnectReason::Flooding.to_string();
+            shared.audit(
+                None,
+                client.id,
+                AuditAction::Moderation(ModerationAction::Kick { reason }),
+            );
+            // the chat state sends the disconnect packet
+            shared.kick(client.id, DisconnectReason::Flooding);
+            return Some(());
+        }
+    };
+    send_packet(socket, shared, ServerChatPacket::Warning { reason }).await
+}
+
+fn account_id(shared: &Shared, member_id: Uuid) -> Option<Uuid> {
+    shared.members.get(&member_id)?.account_id
+}
+
+fn require(role: Role, permission: Permission) -> Result<(), RejectReason> {
+    if role.can(permission) {
+        Ok(())
+    } else {
+        Err(RejectReason::MissingPermission(permission))
+    }
+}
+
+fn send_message(
+    shared: &Shared,
+    client: &mut Client,
+    message_id: Uuid,
+    message: &str,
+) -> Result<(), RejectReason> {
+    let sender_id = client.id;
+    let sender = MessageSender {
+        member_id: sender_id,
+        account_id: account_id(shared, sender_id),
+    };
+
+    // the client retried a message that already went through,
+    // it is acked again without counting against the limits
+    if let Some(earlier) = shared.message_senders.get(message_id) {
+        return if earlier.is(sender.member_id, sender.account_id) {
+            Ok(())
+        } else {
+            Err(RejectReason::DuplicateId)
+        };
+    }
+
+    let config = shared.config();
+    let role = shared.role(sender_id);
+    require(role, Permission::SendMessages)?;
+
+    // admins are not rate limited
+    let admin = role >= Role::Admin;
+
+    // rate limit first, so that spamming
+    // invalid messages gets caught too
+    let limits = &config.rate_limit;
+    if !admin {
+        client
+            .message_limit
+            .take(limits.message_rate, limits.message_burst)
+            .map_err(|retry_after| RejectReason::RateLimited { retry_after })?;
+    }
+
+    let message = validate::message(&config, message)?;
+
+    if let Some(remaining) = shared.muted(sender_id) {
+        return Err(RejectReason::Muted { remaining });
+    }
+
+    let now = Instant::now();
+    if let (false, Some(slow_mode), Some(last)) = (admin, shared.slow_mode(), client.last_message) {
+        if let Some(retry_after) = slow_mode.checked_sub(now.duration_since(last)) {
+            if !retry_after.is_zero() {
+                return Err(RejectReason::SlowMode { retry_after });
+            }
+        }
+    }
+
+    let history_size = config.limits.history_size;
+    match shared
+        .message_senders
+        .claim(message_id, sender, history_size)
+    {
+        Ok(()) => {}
+        // a retry racing the first attempt
+        Err(earlier) if earlier.is(sender.member_id, sender.account_id) => return Ok(()),
+        Err(_) => return Err(RejectReason::DuplicateId),
+    }
+
+    shared.push_history(HistoryMessage {
+        sender_id,
+        message_id,
+        message: message.clone(),
+        timestamp: SystemTime::now(),
+    });
+
+    shared
+        .broadcast
+        .send(ServerPacket::Chat(ServerChatPacket::NewMessage {
+            sender_id,
+            message_id,
+            message,
+        }))
+        .map_err(|_| RejectReason::Custom("Failed to broadcast the message".into()))?;
+    shared.stats.messages.fetch_add(1, Ordering::Relaxed);
+    client.last_message = Some(now);
+
+    Ok(())
+}
+
+/// Members that can't send messages right now aren't shown typing
+fn typing(shared: &Shared, client: &mut Client) {
+    let now = Instant::now();
+    if client
+        .last_typing
+        .is_some_and(|last| now.duration_since(last) < TYPING_INTERVAL)
+    {
+        return;
+    }
+
+    let muted = shared.muted(client.id).is_some();
+    if muted || !shared.role(client.id).can(Permission::SendMessages) {
+        return;
+    }
+
+    client.last_typing = Some(now);
+    let _ = shared.broadcast.send(
+        ServerChatPacket::Typing {
+            member_id: client.id,
+        }
+        .into(),
+    );
+}
+
+fn edit_message(
+    shared: &Shared,
+    client: &Client,
+    message_id: Uuid,
+    message: &str,
+) -> Result<(), RejectReason> {
+    let sender_id = shared
+        .history_sender(message_id)
+        .ok_or(RejectReason::UnknownMessage)?;
+    require(
+        shared.role(client.id),
+        if own_message(shared, client, message_id) {
+            Permission::EditOwnMessages
+        } else {
+            Permission::EditOthersMessages
+        },
+    )?;
+
+    let message = validate::message(&shared.config(), message)?;
+    if let Some(remaining) = shared.muted(client.id) {
+        return Err(RejectReason::Muted { remaining });
+    }
+
+    if !shared.edit_history(message_id, message.clone()) {
+        return Err(RejectReason::UnknownMessage);
+    }
+    info!(%message_id, %sender_id, "Message edited");
+    let _ = shared.broadcast.send(
+        ServerChatPacket::EditMessage {
+            sender_id,
+            message_id,
+            message,
+        }
+        .into(),
+    );
+    Ok(())
+}
+
+/// Sent by the same account, even from an earlier connection
+fn own_message(shared: &Shared, client: &Client, message_id: Uuid) -> bool {
+    shared
+        .message_senders
+        .get(message_id)
+        .is_some_and(|sender| sender.is(client.id, account_id(shared, client.id)))
+}
+
+fn remove_message(shared: &Shared, client: &Client, message_id: Uuid) -> Result<(), RejectReason> {
+    let sender_id = shared
+        .history_sender(message_id)
+        .ok_or(RejectReason::UnknownMessage)?;
+    let own = own_message(shared, client, message_id);
+    require(
+        shared.role(client.id),
+        if own {
+            Permission::DeleteOwnMessages
+        } else {
+            Permission::DeleteOthersMessages
+        },
+    )?;
+
+    // the id stays in `message_senders` for a while, so it can't be reused right away
+    let removed = shared
+        .remove_history(message_id)
+        .ok_or(RejectReason::UnknownMessage)?;
+    info!(%message_id, %sender_id, "Message removed");
+    if !own {
+        shared.audit(
+            Some(client.id),
+            sender_id,
+            AuditAction::RemoveMessage {
+                message_id,
+                message: removed.message,
+            },
+        );
+    }
+    let _ = shared.broadcast.send(
+        ServerChatPacket::RemoveMessage {
+            sender_id,
+            message_id,
+        }
+        .into(),
+    );
+    Ok(())
+}
+
+fn moderate(
+    shared: &Shared,
+    client: &Client,
+    member_id: Uuid,
+    action: ModerationAction,
+) -> Result<(), RejectReason> {
+    let role = shared.role(client.id);
+    require(
+        role,
+        match action {
+            ModerationAction::Kick { .. } => Permission::Kick,
+            ModerationAction::Mute { .. } => Permission::Mute,
+            ModerationAction::Ban { .. } => Permission::Ban,
+            ModerationAction::SetRole { .. } => Permission::ManageRoles,
+            _ => return Err(RejectReason::Unsupported),
+        },
+    )?;
+
+    let Some(account_id) = shared
+        .members
+        .get(&member_id)
+        .map(|member| member.account_id)
+    else {
+        return Err(RejectReason::UnknownMember);
+    };
+    if member_id == client.id || shared.role(member_id) >= role {
+        return Err(RejectReason::Outranked);
+    }
+    if let ModerationAction::SetRole { role: new_role } = action {
+        if account_id.is_none() {
+            return Err(RejectReason::NoAccount);
+        }
+        // guests are the members without an account
+        if new_role >= role || new_role == Role::Guest {
+            return Err(RejectReason::Outranked);
+        }
+    }
+
+    let config = shared.config();
+    let reason = |reason: &str, default: &str| {
+        validate::reason(&config, reason).map(|reason| match reason.as_str() {
+            "" => default.to_string(),
+            _ => reason,
+        })
+    };
+    let action = match action {
+        ModerationAction::Kick { reason: r } => ModerationAction::Kick {
+            reason: reason(&r, "Kicked by a moderator")?,
+        },
+        ModerationAction::Ban {
+            reason: r,
+            duration,
+        } => ModerationAction::Ban {
+            reason: reason(&r, "Banned by a moderator")?,
+            duration,
+        },
+        action => action,
+    };
+
+    // before acting, so that the member sees it too
+    let _ = shared.broadcast.send(
+        ServerChatPacket::Moderation {
+            moderator_id: client.id,
+            member_id,
+            action: action.clone(),
+        }
+        .into(),
+    );
+    shared.moderate(Some(client.id), member_id, action);
+    Ok(())
+}
+
+async fn handle_chat_broadcast(
+    socket: &mut Socket,
+    shared: &Shared,
+    packet: ServerPacket,
+) -> Option<()> {
+    send_packet(socket, shared, packet).await
 }
@@ -0,0 +1,94 @@
+use clap::ValueEnum;
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, IsTerminal, Write},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing_subscriber::{filter::ParseError, EnvFilter};
+
+//
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+/// Sends each log line to the server tui
+struct TuiWriter {
+    sink: UnboundedSender<String>,
+    line: Vec<u8>,
+}
+
+//
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_possible_value()
+                .as_ref()
+                .map(|s| s.get_name())
+                .unwrap_or("<none>")
+        )
+    }
+}
+
+/// Install the global logger
+///
+/// `filter` is a level or `tracing` filter directives.
+/// Logs go to stdout, or to `tui` when it is running.
+pub fn init(
+    filter: &str,
+    format: LogFormat,
+    tui: Option<UnboundedSender<String>>,
+) -> Result<(), ParseError> {
+    let filter = EnvFilter::try_new(filter)?;
+
+    match (tui, format) {
+        // the tui shows its own timestamps and has little room
+        (Some(sink), _) => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_ansi(false)
+            .with_target(false)
+            .without_time()
+            .with_writer(move || TuiWriter {
+                sink: sink.clone(),
+                line: vec![],
+            })
+            .init(),
+        (None, LogFormat::Text) => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_ansi(io::stdout().is_terminal())
+            .init(),
+        (None, LogFormat::Json) => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .json()
+            .init(),
+    }
+
+    Ok(())
+}
+
+impl Write for TuiWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TuiWriter {
+    // one writer is made for each event
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.line);
+        let _ = self.sink.send(line.trim_end().to_string());
+    }
+}
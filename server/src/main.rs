@@ -1,22 +1,49 @@
-#![feature(const_socketaddr)]
-#![feature(try_blocks)]
 #![feature(duration_constants)]
 
 //
 
 use clap::{Parser, ValueEnum};
-use dashmap::DashSet;
+use config::ConfigArgs;
 use eznet::listener::Listener;
+use log::LogFormat;
+use persistence::Saved;
+use state::Shared;
 use std::{
     fmt::{self, Display, Formatter},
     net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    path::PathBuf,
+    process::exit,
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::broadcast::channel;
+use tokio::sync::{broadcast::channel, mpsc::unbounded_channel};
+use tracing::{error, info, warn};
+use tui_chat_app_common::trace::PacketTrace;
 
 //
 
+#[cfg(unix)]
+pub mod admin;
+pub mod audit;
+pub mod bans;
+pub mod config;
+pub mod connections;
 pub mod handler;
+pub mod log;
+pub mod message_senders;
+pub mod metrics;
+pub mod mutes;
+pub mod persistence;
+pub mod rate_limit;
+pub mod setup;
+pub mod shutdown;
+pub mod state;
+pub mod stats;
+pub mod tui;
+pub mod validate;
+
+#[cfg(test)]
+mod test_util;
 
 //
 
@@ -28,21 +55,31 @@ pub static DEFAULT_ADDRESS: SocketAddr =
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
-    /// Server listen address (IPv4 or IPv6)
-    ///
-    /// Note: you might have to surround IPv6 addresses with '' or ""
-    ///
-    /// Examples:
-    /// - 127.0.0.1:1234
-    /// - [::1]:1234
-    /// - 0.0.0.0:1234
-    /// - [::]:1234
-    #[clap(short, long, value_name = "ADDRESS", default_value_t = DEFAULT_ADDRESS)]
-    listen: SocketAddr,
+    #[command(flatten)]
+    config: ConfigArgs,
 
     /// User interface method
     #[clap(short, long, default_value_t = Method::Quiet)]
     method: Method,
+
+    /// TUI update rate in milliseconds between ticks
+    #[clap(short, long, default_value_t = 100)]
+    tui_tick_rate: u16,
+
+    /// Log every sent and received packet to this file
+    #[clap(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Log verbosity
+    ///
+    /// A level (`error`, `warn`, `info`, `debug`, `trace`)
+    /// or filter directives like `tui_chat_app_server=debug`
+    #[clap(long, value_name = "FILTER", default_value = "info")]
+    log_level: String,
+
+    /// Log output format
+    #[clap(long, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -50,7 +87,7 @@ pub enum Method {
     /// Terminal User Interface
     Tui,
 
-    /// Prompts to config stuff
+    /// Prompts to write a config file, then be quiet
     Prompts,
 
     /// Use cli arguments and be quiet
@@ -75,26 +112,128 @@ impl Display for Method {
 #[tokio::main]
 async fn main() {
     // parse cli
-    let CliArgs { listen, method } = CliArgs::parse();
+    let CliArgs {
+        config,
+        method,
+        tui_tick_rate,
+        trace,
+        log_level,
+        log_format,
+    } = CliArgs::parse();
+
+    // logs are shown in the tui while it runs
+    let (tui_log, tui_log_recv) = match method {
+        Method::Tui => {
+            let (send, recv) = unbounded_channel();
+            (Some(send), Some(recv))
+        }
+        _ => (None, None),
+    };
+    if let Err(err) = log::init(&log_level, log_format, tui_log) {
+        eprintln!("Invalid --log-level: {err}");
+        exit(1);
+    }
 
-    match method {
-        Method::Tui => {}
-        Method::Prompts => {}
-        Method::Quiet => {}
+    let mut config_args = config;
+    if let Method::Prompts = method {
+        match setup::run(&config_args) {
+            Ok(path) => config_args.config = Some(path),
+            Err(err) => {
+                eprintln!("Setup failed: {err}");
+                exit(1);
+            }
+        }
     }
 
-    // start listening for connections
-    let mut listener = Listener::bind(listen);
+    let config = match config_args.load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+
+    let trace = match trace {
+        Some(path) => match PacketTrace::open(&path) {
+            Ok(trace) => trace,
+            Err(err) => {
+                eprintln!("Failed to open packet trace file {}: {err}", path.display());
+                exit(1);
+            }
+        },
+        None => PacketTrace::default(),
+    };
+
+    let saved = match &config.persistence.dir {
+        Some(dir) => match persistence::load(dir) {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        },
+        None => Saved::default(),
+    };
 
-    let connections = Arc::new(DashSet::new());
+    // start listening for connections
+    let listener = Listener::bind(config.server.listen);
+
+    let (send, _) = channel(config.limits.broadcast_buffer);
+    let shared = Arc::new(Shared::new(config, config_args, send, trace, saved));
+
+    tokio::spawn(persistence::saver(shared.clone()));
+    tokio::spawn(metrics::run(shared.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(shared.clone()));
+    #[cfg(unix)]
+    tokio::spawn(admin::run(shared.clone()));
+
+    // the listener is never dropped, new connections
+    // are refused while the server is shutting down
+    tokio::spawn(accept(listener, shared.clone()));
+
+    match (method, tui_log_recv) {
+        (Method::Tui, Some(log)) => {
+            // closing the tui stops the server, and so does a signal
+            let signalled = shared.clone();
+            tokio::spawn(async move {
+                shutdown::signal().await;
+                signalled.start_shutdown();
+            });
+
+            let tick_rate = Duration::from_millis(tui_tick_rate as _);
+            if let Err(err) = tui::run(shared.clone(), log, tick_rate).await {
+                eprintln!("Server tui failed: {err}");
+                shutdown::run(&shared).await;
+                exit(1);
+            }
+        }
+        _ => shutdown::signal().await,
+    }
 
-    let (send, recv) = channel(256);
-    let send = Arc::new(send);
+    shutdown::run(&shared).await;
+    exit(0);
+}
 
+async fn accept(mut listener: Listener, shared: Arc<Shared>) {
     while let Some(conn) = listener.next().await {
-        let send = send.clone();
-        let recv = recv.resubscribe();
+        tokio::spawn(handler::handler(conn, shared.clone()));
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(shared: Arc<Shared>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+        warn!("Failed to listen for SIGHUP, config reloading is disabled");
+        return;
+    };
 
-        tokio::spawn(handler::handler(conn, connections.clone(), send, recv));
+    while hangup.recv().await.is_some() {
+        match shared.reload_config() {
+            Ok(()) => info!("Config reloaded"),
+            Err(err) => error!("Config not reloaded: {err}"),
+        }
     }
 }
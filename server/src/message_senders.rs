@@ -0,0 +1,152 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+//
+
+/// Senders of the latest message ids,
+/// used to catch reused ids and retries
+///
+/// Only the newest ids are kept, so that
+/// a long running server doesn't grow it forever
+#[derive(Debug, Default)]
+pub struct MessageSenders {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Oldest first
+    order: VecDeque<Uuid>,
+    senders: HashMap<Uuid, MessageSender>,
+}
+
+/// Who sent a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSender {
+    pub member_id: Uuid,
+
+    /// `None` for guests
+    pub account_id: Option<Uuid>,
+}
+
+//
+
+/// Ids kept even if the history is shorter
+pub const MIN_KEPT_IDS: usize = 4096;
+
+//
+
+impl MessageSenders {
+    /// `messages` are `(message id, sender)`, oldest first
+    pub fn new(messages: impl IntoIterator<Item = (Uuid, MessageSender)>) -> Self {
+        let mut inner = Inner::default();
+        for (message_id, sender_id) in messages {
+            if inner.senders.insert(message_id, sender_id).is_none() {
+                inner.order.push_back(message_id);
+            }
+        }
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Sender of a recent message id
+    pub fn get(&self, message_id: Uuid) -> Option<MessageSender> {
+        self.inner.lock().unwrap().senders.get(&message_id).copied()
+    }
+
+    /// Remember the sender of a new message id
+    ///
+    /// Err is the earlier sender if the id is already taken.
+    /// At least the ids of the last `history_size`
+    /// messages are kept, older ones are forgotten.
+    pub fn claim(
+        &self,
+        message_id: Uuid,
+        sender: MessageSender,
+        history_size: usize,
+    ) -> Result<(), MessageSender> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(earlier) = inner.senders.get(&message_id) {
+            return Err(*earlier);
+        }
+
+        let kept = history_size.max(MIN_KEPT_IDS);
+        while inner.order.len() >= kept {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+            inner.senders.remove(&oldest);
+        }
+
+        inner.senders.insert(message_id, sender);
+        inner.order.push_back(message_id);
+        Ok(())
+    }
+}
+
+impl MessageSender {
+    /// Members with an account own the messages of their
+    /// earlier connections too, guests only their own
+    pub fn is(&self, member_id: Uuid, account_id: Option<Uuid>) -> bool {
+        match (self.account_id, account_id) {
+            (Some(sender), Some(account_id)) => sender == account_id,
+            _ => self.member_id == member_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest() -> MessageSender {
+        MessageSender {
+            member_id: Uuid::new_v4(),
+            account_id: None,
+        }
+    }
+
+    #[test]
+    fn claim_rejects_taken_ids() {
+        let senders = MessageSenders::default();
+        let (message, alice, bob) = (Uuid::new_v4(), guest(), guest());
+
+        assert_eq!(senders.claim(message, alice, 10), Ok(()));
+        assert_eq!(senders.claim(message, bob, 10), Err(alice));
+        assert_eq!(senders.get(message), Some(alice));
+    }
+
+    #[test]
+    fn oldest_ids_are_forgotten() {
+        let sender = guest();
+        let ids: Vec<Uuid> = (0..MIN_KEPT_IDS + 1).map(|_| Uuid::new_v4()).collect();
+        let senders = MessageSenders::new(ids[..MIN_KEPT_IDS].iter().map(|id| (*id, sender)));
+
+        senders.claim(ids[MIN_KEPT_IDS], sender, 0).unwrap();
+        assert_eq!(senders.get(ids[0]), None);
+        assert_eq!(senders.get(ids[1]), Some(sender));
+        assert_eq!(senders.get(ids[MIN_KEPT_IDS]), Some(sender));
+    }
+
+    #[test]
+    fn accounts_own_messages_across_connections() {
+        let account_id = Some(Uuid::new_v4());
+        let sender = MessageSender {
+            member_id: Uuid::new_v4(),
+            account_id,
+        };
+
+        // reconnected with a new member id
+        assert!(sender.is(Uuid::new_v4(), account_id));
+        assert!(!sender.is(sender.member_id, Some(Uuid::new_v4())));
+
+        let guest = guest();
+        assert!(guest.is(guest.member_id, None));
+        assert!(!guest.is(Uuid::new_v4(), None));
+        assert!(!guest.is(Uuid::new_v4(), account_id));
+    }
+}
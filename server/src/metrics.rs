@@ -0,0 +1,308 @@
+use crate::{persistence, state::Shared, stats::Stats};
+use std::{
+    fmt::{Display, Write as _},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info};
+
+//
+
+/// Messages per second over the last `metrics.interval_secs`
+#[derive(Debug, Default)]
+struct Rates {
+    sampled: Option<(Instant, u64)>,
+    messages_per_second: f64,
+}
+
+//
+
+/// Time for a scraper to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_REQUEST_BYTES: usize = 4096;
+
+/// How soon a reloaded `metrics.listen` is picked up
+const LISTEN_CHECK_INTERVAL: Duration = Duration::SECOND;
+
+//
+
+/// Serve the metrics over HTTP and write them to the metrics file
+pub async fn run(shared: Arc<Shared>) {
+    let rates = Arc::new(Mutex::new(Rates::default()));
+    tokio::spawn(listen(shared.clone(), rates.clone()));
+
+    loop {
+        // the config might have been reloaded
+        let interval = Duration::from_secs(shared.config().metrics.interval_secs);
+        tokio::time::sleep(interval).await;
+
+        rates.lock().unwrap().sample(&shared);
+
+        let config = shared.config();
+        if let Some(path) = &config.metrics.file {
+            let metrics = render(&shared.stats, shared.members.len(), &rates.lock().unwrap());
+            if let Err(err) = persistence::write_atomic(path, metrics.as_bytes()) {
+                error!(path = %path.display(), "Failed to write metrics: {err}");
+            }
+        }
+    }
+}
+
+/// Serve on `metrics.listen`, moving to the new address when a reload changes it
+///
+/// A failed bind is retried until it succeeds or the address changes
+async fn listen(shared: Arc<Shared>, rates: Arc<Mutex<Rates>>) {
+    // the address that is being served
+    let mut current = None;
+    let mut server: Option<JoinHandle<()>> = None;
+    // only the first failure of an address is logged as an error
+    let mut failed = None;
+    loop {
+        let listen = shared.config().metrics.listen;
+        if listen != current {
+            if let (Some(server), Some(addr)) = (server.take(), current.take()) {
+                server.abort();
+                info!(%addr, "Stopped serving metrics");
+            }
+
+            if let Some(addr) = listen {
+                match TcpListener::bind(addr).await {
+                    Ok(listener) => {
+                        info!(%addr, "Serving metrics");
+                        server = Some(tokio::spawn(serve(listener, shared.clone(), rates.clone())));
+                        current = Some(addr);
+                        failed = None;
+                    }
+                    Err(err) if failed == Some(addr) => {
+                        debug!(%addr, "Failed to listen for metrics requests: {err}");
+                    }
+                    Err(err) => {
+                        error!(%addr, "Failed to listen for metrics requests, retrying: {err}");
+                        failed = Some(addr);
+                    }
+                }
+            }
+        }
+        tokio::time::sleep(LISTEN_CHECK_INTERVAL).await;
+    }
+}
+
+async fn serve(listener: TcpListener, shared: Arc<Shared>, rates: Arc<Mutex<Rates>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream, shared.clone(), rates.clone()));
+            }
+            Err(err) => {
+                // most likely out of file descriptors, give them time to close
+                error!("Failed to accept a metrics request: {err}");
+                tokio::time::sleep(Duration::SECOND).await;
+            }
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, shared: Arc<Shared>, rates: Arc<Mutex<Rates>>) {
+    // only the request line matters, the headers are read and ignored
+    let mut request = Vec::new();
+    let read = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut buf = [0; 512];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            if request.len() >= MAX_REQUEST_BYTES {
+                return false;
+            }
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        true
+    })
+    .await;
+    if read != Ok(true) {
+        debug!("Invalid metrics request");
+        return;
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics" | "/")) => {
+            let metrics = render(&shared.stats, shared.members.len(), &rates.lock().unwrap());
+            ("200 OK", metrics)
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\
+        \r\n\
+        {body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Metrics in the Prometheus text format
+fn render(stats: &Stats, members: usize, rates: &Rates) -> String {
+    let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    let mut out = String::new();
+    metric(&mut out, "members", "gauge", "Members in the chat", members);
+    metric(
+        &mut out,
+        "messages_total",
+        "counter",
+        "Chat messages broadcast",
+        counter(&stats.messages),
+    );
+    metric(
+        &mut out,
+        "messages_per_second",
+        "gauge",
+        "Chat messages per second over the last metrics interval",
+        rates.messages_per_second,
+    );
+    metric(
+        &mut out,
+        "packets_received_total",
+        "counter",
+        "Packets received from clients",
+        counter(&stats.packets_in),
+    );
+    metric(
+        &mut out,
+        "packets_sent_total",
+        "counter",
+        "Packets sent to clients",
+        counter(&stats.packets_out),
+    );
+    metric(
+        &mut out,
+        "bytes_received_total",
+        "counter",
+        "Packet bytes received from clients",
+        counter(&stats.bytes_in),
+    );
+    metric(
+        &mut out,
+        "bytes_sent_total",
+        "counter",
+        "Packet bytes sent to clients",
+        counter(&stats.bytes_out),
+    );
+    metric(
+        &mut out,
+        "lag_events_total",
+        "counter",
+        "Times a member lagged behind the broadcast",
+        counter(&stats.lag_events),
+    );
+    metric(
+        &mut out,
+        "lagged_packets_total",
+        "counter",
+        "Broadcast packets skipped by lagging members",
+        counter(&stats.lagged_packets),
+    );
+
+    header(
+        &mut out,
+        "handshake_failures_total",
+        "counter",
+        "Refused connections and failed handshakes",
+    );
+    for (reason, count) in stats.handshake_failures() {
+        let _ = writeln!(
+            out,
+            "tui_chat_handshake_failures_total{{reason=\"{reason}\"}} {count}"
+        );
+    }
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "tui_chat_{name} {value}");
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP tui_chat_{name} {help}");
+    let _ = writeln!(out, "# TYPE tui_chat_{name} {kind}");
+}
+
+impl Rates {
+    fn sample(&mut self, shared: &Shared) {
+        let now = Instant::now();
+        let messages = shared.stats.messages.load(Ordering::Relaxed);
+        if let Some((at, last)) = self.sampled {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            if elapsed > 0.0 {
+                self.messages_per_second = messages.saturating_sub(last) as f64 / elapsed;
+            }
+        }
+        self.sampled = Some((now, messages));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tui_chat_app_common::server::ServerInitFailReason;
+
+    #[test]
+    fn render_prometheus_text() {
+        let stats = Stats::default();
+        stats.messages.store(42, Ordering::Relaxed);
+        stats.packet_in(100);
+        stats.handshake_failed(&ServerInitFailReason::ServerFull);
+        stats.handshake_failed(&ServerInitFailReason::ServerFull);
+        let rates = Rates {
+            sampled: None,
+            messages_per_second: 1.5,
+        };
+
+        let out = render(&stats, 3, &rates);
+        let lines: Vec<&str> = out.lines().collect();
+
+        for line in [
+            "# HELP tui_chat_members Members in the chat",
+            "# TYPE tui_chat_members gauge",
+            "tui_chat_members 3",
+            "# TYPE tui_chat_messages_total counter",
+            "tui_chat_messages_total 42",
+            "tui_chat_messages_per_second 1.5",
+            "tui_chat_packets_received_total 1",
+            "tui_chat_bytes_received_total 100",
+            "tui_chat_lag_events_total 0",
+            "# TYPE tui_chat_handshake_failures_total counter",
+            "tui_chat_handshake_failures_total{reason=\"server_full\"} 2",
+        ] {
+            assert!(lines.contains(&line), "missing `{line}` in\n{out}");
+        }
+
+        // every sample has its help and type
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(
+                lines.contains(&format!("# TYPE {name} counter").as_str())
+                    || lines.contains(&format!("# TYPE {name} gauge").as_str())
+            );
+        }
+    }
+}
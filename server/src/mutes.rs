@@ -0,0 +1,179 @@
+use crate::bans::{BanTarget, IpRange};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, sync::RwLock, time::Duration};
+use uuid::Uuid;
+
+//
+
+/// Every active mute, kept apart from the connections
+/// so that reconnecting doesn't end a mute
+#[derive(Debug, Default)]
+pub struct Mutes {
+    list: RwLock<Vec<Mute>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mute {
+    /// An account or an address, a member is muted by both
+    #[serde(flatten)]
+    pub target: BanTarget,
+
+    pub expires: DateTime<Utc>,
+}
+
+/// Longer mutes are cut to this
+pub const MAX_MUTE: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+//
+
+impl Mutes {
+    pub fn new(list: Vec<Mute>) -> Self {
+        Self {
+            list: RwLock::new(list),
+        }
+    }
+
+    /// Time left in the longest mute of this client, if any
+    ///
+    /// Matches the address too, so that logging in with another
+    /// account or as a guest doesn't end the mute
+    pub fn find(&self, ip: IpAddr, account_id: Option<Uuid>) -> Option<Duration> {
+        let now = Utc::now();
+        self.list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|mute| mute.target.matches(ip, account_id))
+            .filter_map(|mute| (mute.expires - now).to_std().ok())
+            .max()
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Replaces an older mute of the same target
+    pub fn add(&self, mute: Mute) {
+        let mut list = self.list.write().unwrap();
+        list.retain(|old| old.target != mute.target);
+        list.push(mute);
+    }
+
+    /// Remove every mute of this client, false if it wasn't muted
+    pub fn remove(&self, ip: IpAddr, account_id: Option<Uuid>) -> bool {
+        let mut list = self.list.write().unwrap();
+        let len = list.len();
+        list.retain(|mute| !mute.target.matches(ip, account_id));
+        list.len() != len
+    }
+
+    /// Active mutes, expired mutes are dropped
+    pub fn list(&self) -> Vec<Mute> {
+        let now = Utc::now();
+        let mut list = self.list.write().unwrap();
+        list.retain(|mute| mute.expires > now);
+        list.clone()
+    }
+
+    pub fn replace(&self, list: Vec<Mute>) {
+        *self.list.write().unwrap() = list;
+    }
+}
+
+impl Mute {
+    pub fn new(target: BanTarget, duration: Duration) -> Self {
+        let duration = chrono::Duration::from_std(duration.min(MAX_MUTE))
+            .expect("MAX_MUTE fits in a chrono::Duration");
+        Self {
+            target,
+            expires: Utc::now() + duration,
+        }
+    }
+
+    /// Members are muted by their address and their account, if they have one
+    pub fn targets(ip: IpAddr, account_id: Option<Uuid>) -> Vec<BanTarget> {
+        let mut targets = vec![BanTarget::Ip(IpRange::from(ip))];
+        targets.extend(account_id.map(BanTarget::Account));
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn mute(target: BanTarget, secs: i64) -> Mute {
+        Mute {
+            target,
+            expires: Utc::now() + chrono::Duration::seconds(secs),
+        }
+    }
+
+    #[test]
+    fn long_mutes_are_cut() {
+        let mute = Mute::new(BanTarget::Account(Uuid::new_v4()), Duration::MAX);
+        assert!(mute.expires <= Utc::now() + chrono::Duration::from_std(MAX_MUTE).unwrap());
+    }
+
+    #[test]
+    fn accounts_are_muted_on_every_address() {
+        let account_id = Uuid::new_v4();
+        let mutes = Mutes::new(vec![mute(BanTarget::Account(account_id), 60)]);
+
+        assert!(mutes.find(ip("10.0.0.1"), Some(account_id)).is_some());
+        assert!(mutes.find(ip("2001:db8::1"), Some(account_id)).is_some());
+        assert!(mutes.find(ip("10.0.0.1"), Some(Uuid::new_v4())).is_none());
+        assert!(mutes.find(ip("10.0.0.1"), None).is_none());
+    }
+
+    #[test]
+    fn addresses_are_muted_with_any_account() {
+        let mutes = Mutes::default();
+        mutes.add(mute(BanTarget::Ip(IpRange::from(ip("10.0.0.1"))), 60));
+
+        // a dual stack listener sees the same client as `::ffff:10.0.0.1`
+        assert!(mutes.find(ip("::ffff:10.0.0.1"), None).is_some());
+        assert!(mutes.find(ip("10.0.0.2"), None).is_none());
+        assert!(mutes.find(ip("10.0.0.1"), Some(Uuid::new_v4())).is_some());
+    }
+
+    #[test]
+    fn reconnecting_as_a_guest_stays_muted() {
+        let account_id = Uuid::new_v4();
+        let mutes = Mutes::default();
+        for target in Mute::targets(ip("10.0.0.1"), Some(account_id)) {
+            mutes.add(mute(target, 60));
+        }
+
+        assert!(mutes.find(ip("10.0.0.1"), None).is_some());
+        assert!(mutes.find(ip("10.0.0.1"), Some(Uuid::new_v4())).is_some());
+        // and the account from another address
+        assert!(mutes.find(ip("10.0.0.2"), Some(account_id)).is_some());
+    }
+
+    #[test]
+    fn expired_mutes_are_dropped() {
+        let mutes = Mutes::new(vec![
+            mute(BanTarget::Ip("10.0.0.1".parse().unwrap()), -1),
+            mute(BanTarget::Ip("10.0.0.2".parse().unwrap()), 60),
+        ]);
+
+        assert!(mutes.find(ip("10.0.0.1"), None).is_none());
+        assert_eq!(mutes.list().len(), 1);
+    }
+
+    #[test]
+    fn remove_unmutes() {
+        let account_id = Uuid::new_v4();
+        let mutes = Mutes::new(vec![
+            mute(BanTarget::Ip(IpRange::from(ip("10.0.0.1"))), 60),
+            mute(BanTarget::Account(account_id), 60),
+        ]);
+
+        assert!(mutes.remove(ip("10.0.0.1"), Some(account_id)));
+        assert!(!mutes.remove(ip("10.0.0.1"), Some(account_id)));
+        assert!(mutes.find(ip("10.0.0.1"), Some(account_id)).is_none());
+    }
+}
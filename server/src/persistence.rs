@@ -0,0 +1,520 @@
+use crate::{audit::RECENT_ENTRIES, bans::Ban, mutes::Mute, state::Shared};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{error, warn};
+use tui_chat_app_common::{
+    role::Role,
+    server::{AuditEntry, HistoryMessage},
+};
+use uuid::Uuid;
+
+//
+
+/// How often a changed history and read markers are written to disk
+pub static SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Everything saved in the data directory
+#[derive(Default)]
+pub struct Saved {
+    pub history: Vec<SavedMessage>,
+    pub bans: Vec<Ban>,
+    pub mutes: Vec<Mute>,
+    pub roles: HashMap<Uuid, Role>,
+    pub audit_log: Vec<AuditEntry>,
+    /// The newest read message by account id
+    pub read_markers: HashMap<Uuid, Uuid>,
+}
+
+/// Held while a file is saved, so that concurrent saves
+/// of the same file can't overwrite a newer snapshot
+///
+/// Take the snapshot of the data after locking
+#[derive(Debug, Default)]
+pub struct SaveLocks {
+    pub bans: Mutex<()>,
+    pub mutes: Mutex<()>,
+    pub roles: Mutex<()>,
+}
+
+/// A history message and the account that sent it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedMessage {
+    #[serde(flatten)]
+    pub message: HistoryMessage,
+
+    /// `None` for guests and in histories saved before accounts were
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<Uuid>,
+}
+
+//
+
+/// Load everything that was saved in `dir`
+pub fn load(dir: &Path) -> Result<Saved, String> {
+    let error =
+        |what: &str, err: io::Error| format!("Failed to load {what} from {}: {err}", dir.display());
+    Ok(Saved {
+        history: load_history(dir).map_err(|err| error("message history", err))?,
+        bans: load_bans(dir).map_err(|err| error("bans", err))?,
+        mutes: load_mutes(dir).map_err(|err| error("mutes", err))?,
+        roles: load_roles(dir).map_err(|err| error("roles", err))?,
+        audit_log: load_audit_log(dir).map_err(|err| error("audit log", err))?,
+        read_markers: load_read_markers(dir).map_err(|err| error("read markers", err))?,
+    })
+}
+
+/// Periodically save the message history and the read markers
+/// if [`crate::config::PersistenceConfig::dir`] is set
+pub async fn saver(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        save_dirty(&shared);
+    }
+}
+
+/// Save the history and the read markers if they changed since the last save
+pub fn save_dirty(shared: &Shared) {
+    // the config might have been reloaded
+    let Some(dir) = shared.config().persistence.dir.clone() else {
+        return;
+    };
+
+    if let Some(history) = shared.take_dirty_history() {
+        if let Err(err) = save_history(&dir, &history) {
+            error!(dir = %dir.display(), "Failed to save message history: {err}");
+        }
+    }
+    if let Some(read_markers) = shared.take_dirty_read_markers() {
+        if let Err(err) = save_read_markers(&dir, &read_markers) {
+            error!(dir = %dir.display(), "Failed to save read markers: {err}");
+        }
+    }
+}
+
+fn history_path(dir: &Path) -> PathBuf {
+    dir.join("history.json")
+}
+
+/// Empty if nothing was saved yet
+pub fn load_history(dir: &Path) -> io::Result<Vec<SavedMessage>> {
+    match fs::read(history_path(dir)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_history(dir: &Path, history: &[SavedMessage]) -> io::Result<()> {
+    write_atomic(&history_path(dir), &serde_json::to_vec(history)?)
+}
+
+fn bans_path(dir: &Path) -> PathBuf {
+    dir.join("bans.toml")
+}
+
+/// `[[ban]]` tables
+#[derive(Default, Serialize, Deserialize)]
+struct BanFile {
+    #[serde(default, rename = "ban")]
+    bans: Vec<Ban>,
+}
+
+/// Empty if nothing was saved yet
+pub fn load_bans(dir: &Path) -> io::Result<Vec<Ban>> {
+    match fs::read_to_string(bans_path(dir)) {
+        Ok(s) => toml::from_str::<BanFile>(&s)
+            .map(|file| file.bans)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_bans(dir: &Path, bans: &[Ban]) -> io::Result<()> {
+    let toml = toml::to_string_pretty(&BanFile {
+        bans: bans.to_vec(),
+    })
+    .map_err(io::Error::other)?;
+    write_atomic(&bans_path(dir), toml.as_bytes())
+}
+
+fn mutes_path(dir: &Path) -> PathBuf {
+    dir.join("mutes.toml")
+}
+
+/// `[[mute]]` tables
+#[derive(Default, Serialize, Deserialize)]
+struct MuteFile {
+    #[serde(default, rename = "mute")]
+    mutes: Vec<Mute>,
+}
+
+/// Empty if nothing was saved yet
+pub fn load_mutes(dir: &Path) -> io::Result<Vec<Mute>> {
+    match fs::read_to_string(mutes_path(dir)) {
+        Ok(s) => toml::from_str::<MuteFile>(&s)
+            .map(|file| file.mutes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_mutes(dir: &Path, mutes: &[Mute]) -> io::Result<()> {
+    let toml = toml::to_string_pretty(&MuteFile {
+        mutes: mutes.to_vec(),
+    })
+    .map_err(io::Error::other)?;
+    write_atomic(&mutes_path(dir), toml.as_bytes())
+}
+
+fn roles_path(dir: &Path) -> PathBuf {
+    dir.join("roles.toml")
+}
+
+/// `<account id> = "<role>"` under `[roles]`
+#[derive(Default, Serialize, Deserialize)]
+struct RoleFile {
+    #[serde(default)]
+    roles: HashMap<Uuid, Role>,
+}
+
+/// Empty if nothing was saved yet
+pub fn load_roles(dir: &Path) -> io::Result<HashMap<Uuid, Role>> {
+    match fs::read_to_string(roles_path(dir)) {
+        Ok(s) => toml::from_str::<RoleFile>(&s)
+            .map(|file| file.roles)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_roles(dir: &Path, roles: HashMap<Uuid, Role>) -> io::Result<()> {
+    let toml = toml::to_string_pretty(&RoleFile { roles }).map_err(io::Error::other)?;
+    write_atomic(&roles_path(dir), toml.as_bytes())
+}
+
+fn read_markers_path(dir: &Path) -> PathBuf {
+    dir.join("read_markers.json")
+}
+
+/// Empty if nothing was saved yet
+pub fn load_read_markers(dir: &Path) -> io::Result<HashMap<Uuid, Uuid>> {
+    match fs::read(read_markers_path(dir)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_read_markers(dir: &Path, read_markers: &HashMap<Uuid, Uuid>) -> io::Result<()> {
+    write_atomic(&read_markers_path(dir), &serde_json::to_vec(read_markers)?)
+}
+
+fn audit_path(dir: &Path) -> PathBuf {
+    dir.join("audit.jsonl")
+}
+
+/// The latest entries, empty if nothing was saved yet
+///
+/// Lines that don't parse are skipped, a crash
+/// while appending can leave a truncated last line
+pub fn load_audit_log(dir: &Path) -> io::Result<Vec<AuditEntry>> {
+    let file = match fs::File::open(audit_path(dir)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    let mut entries = VecDeque::with_capacity(RECENT_ENTRIES);
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!(line = i + 1, "Skipped an invalid audit log entry: {err}");
+                continue;
+            }
+        };
+        if entries.len() >= RECENT_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+    Ok(entries.into())
+}
+
+/// The audit log is only ever appended to, one JSON entry per line
+pub fn append_audit_log(dir: &Path, entry: &AuditEntry) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(audit_path(dir))?;
+
+    // don't continue a line truncated by a crash
+    let mut line = Vec::new();
+    if file.seek(SeekFrom::End(0))? != 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last != *b"\n" {
+            line.push(b'\n');
+        }
+    }
+
+    serde_json::to_writer(&mut line, entry)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Write to a temporary file and rename it over the
+/// old one, so that a crash can't leave a half written file
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // unique, so that concurrent writes can't tear each other
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("{}.{n}.tmp", process::id()));
+
+    if let Err(err) = fs::write(&tmp, bytes).and_then(|_| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bans::BanTarget, test_util::TempDir};
+    use std::time::SystemTime;
+    use tui_chat_app_common::{
+        client::ModerationAction,
+        server::{AuditAction, AuditMember},
+    };
+
+    #[test]
+    fn bans_round_trip() {
+        let dir = TempDir::new();
+        let bans = vec![
+            Ban {
+                target: BanTarget::Ip("10.0.0.0/8".parse().unwrap()),
+                reason: "spam".to_string(),
+                expires: None,
+            },
+            Ban {
+                target: BanTarget::Ip("2001:db8::/32".parse().unwrap()),
+                reason: "more spam".to_string(),
+                expires: Some("2030-01-02T03:04:05Z".parse().unwrap()),
+            },
+            Ban {
+                target: BanTarget::Account(Uuid::new_v4()),
+                reason: String::new(),
+                expires: None,
+            },
+        ];
+
+        save_bans(&dir, &bans).unwrap();
+        let loaded = load_bans(&dir).unwrap();
+
+        assert_eq!(loaded.len(), bans.len());
+        for (loaded, ban) in loaded.iter().zip(&bans) {
+            assert_eq!(loaded.target, ban.target);
+            assert_eq!(loaded.reason, ban.reason);
+            assert_eq!(loaded.expires, ban.expires);
+        }
+    }
+
+    #[test]
+    fn hand_written_ban_file() {
+        let file = r#"
+            [[ban]]
+            ip = "192.168.1.7"
+            reason = "spam"
+
+            [[ban]]
+            account = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            reason = "raid"
+            expires = "2030-01-02T03:04:05Z"
+        "#;
+
+        let bans = toml::from_str::<BanFile>(file).unwrap().bans;
+        assert_eq!(bans.len(), 2);
+        assert_eq!(
+            bans[0].target,
+            BanTarget::Ip("192.168.1.7".parse().unwrap())
+        );
+        assert_eq!(bans[0].expires, None);
+        assert_eq!(
+            bans[1].target,
+            BanTarget::Account("67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap())
+        );
+        assert!(bans[1].expires.is_some());
+    }
+
+    #[test]
+    fn missing_ban_file_is_empty() {
+        let dir = TempDir::new();
+        let bans = load_bans(&dir).unwrap();
+        assert!(bans.is_empty());
+    }
+
+    #[test]
+    fn history_keeps_sender_accounts() {
+        let dir = TempDir::new();
+        let message = |account_id| SavedMessage {
+            message: HistoryMessage {
+                sender_id: Uuid::new_v4(),
+                message_id: Uuid::new_v4(),
+                message: "hi".to_string(),
+                timestamp: SystemTime::now(),
+            },
+            account_id,
+        };
+        let history = vec![message(Some(Uuid::new_v4())), message(None)];
+        save_history(&dir, &history).unwrap();
+
+        let loaded = load_history(&dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (saved, loaded) in history.iter().zip(&loaded) {
+            assert_eq!(saved.message.message_id, loaded.message.message_id);
+            assert_eq!(saved.message.timestamp, loaded.message.timestamp);
+            assert_eq!(saved.account_id, loaded.account_id);
+        }
+    }
+
+    #[test]
+    fn history_without_accounts_loads() {
+        let dir = TempDir::new();
+        let old = vec![HistoryMessage {
+            sender_id: Uuid::new_v4(),
+            message_id: Uuid::new_v4(),
+            message: "hi".to_string(),
+            timestamp: SystemTime::now(),
+        }];
+        fs::write(history_path(&dir), serde_json::to_vec(&old).unwrap()).unwrap();
+
+        let loaded = load_history(&dir).unwrap();
+        assert_eq!(loaded[0].message.message_id, old[0].message_id);
+        assert_eq!(loaded[0].account_id, None);
+    }
+
+    #[test]
+    fn mutes_round_trip() {
+        let dir = TempDir::new();
+        let mutes = vec![
+            Mute::new(BanTarget::Account(Uuid::new_v4()), Duration::from_secs(60)),
+            Mute::new(
+                BanTarget::Ip("10.0.0.1".parse().unwrap()),
+                Duration::from_secs(60),
+            ),
+        ];
+        save_mutes(&dir, &mutes).unwrap();
+
+        let loaded = load_mutes(&dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (saved, loaded) in mutes.iter().zip(&loaded) {
+            assert_eq!(saved.target, loaded.target);
+            assert_eq!(saved.expires, loaded.expires);
+        }
+    }
+
+    #[test]
+    fn concurrent_atomic_writes() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let path = &path;
+                s.spawn(move || {
+                    for _ in 0..50 {
+                        write_atomic(path, format!("writer {i}").as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        let content = fs::read_to_string(&path).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert!(content.starts_with("writer "));
+        assert_eq!(files, 1, "temporary files were left behind");
+    }
+
+    fn audit_entry(reason: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: SystemTime::now(),
+            actor: None,
+            target: AuditMember {
+                member_id: Uuid::new_v4(),
+                account_id: None,
+            },
+            action: AuditAction::Moderation(ModerationAction::Kick {
+                reason: reason.to_string(),
+            }),
+        }
+    }
+
+    fn audit_reasons(entries: &[AuditEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| match &entry.action {
+                AuditAction::Moderation(ModerationAction::Kick { reason }) => reason.as_str(),
+                action => panic!("unexpected {action:?}"),
+            })
+            .collect()
+    }
+
+    /// Half of an entry, like a crash in the middle of an append leaves
+    fn append_truncated(dir: &Path, reason: &str) {
+        let line = serde_json::to_string(&audit_entry(reason)).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(audit_path(dir))
+            .unwrap()
+            .write_all(&line.as_bytes()[..line.len() / 2])
+            .unwrap();
+    }
+
+    #[test]
+    fn truncated_audit_entry_is_skipped() {
+        let dir = TempDir::new();
+        append_audit_log(&dir, &audit_entry("first")).unwrap();
+        append_audit_log(&dir, &audit_entry("second")).unwrap();
+        append_truncated(&dir, "third");
+
+        let loaded = load_audit_log(&dir).unwrap();
+        assert_eq!(audit_reasons(&loaded), ["first", "second"]);
+    }
+
+    #[test]
+    fn append_after_truncated_audit_entry() {
+        let dir = TempDir::new();
+        append_audit_log(&dir, &audit_entry("first")).unwrap();
+        append_truncated(&dir, "second");
+        append_audit_log(&dir, &audit_entry("third")).unwrap();
+
+        let loaded = load_audit_log(&dir).unwrap();
+        assert_eq!(audit_reasons(&loaded), ["first", "third"]);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::RateLimitConfig;
+use std::time::{Duration, Instant};
+
+//
+
+/// Token bucket rate limiter
+///
+/// `rate` and `burst` are given on every
+/// call so that config changes apply
+/// to existing buckets.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f32,
+    last: Instant,
+}
+
+/// Escalating responses to a member
+/// that keeps hitting the rate limits
+#[derive(Debug, Clone, Default)]
+pub struct Flood {
+    strikes: u32,
+    mutes: u32,
+    last_strike: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Penalty {
+    Warn { warnings_left: u32 },
+    Mute(Duration),
+    Kick,
+}
+
+//
+
+/// Warnings are forgotten after this long without hitting a limit
+const STRIKE_RESET: Duration = Duration::from_secs(60);
+
+/// Longest wait reported by [`TokenBucket::take`], tiny rates would overflow
+const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+//
+
+impl TokenBucket {
+    /// A full bucket
+    pub fn new(burst: u32) -> Self {
+        Self {
+            tokens: burst as f32,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take one token
+    ///
+    /// Err is the time until the next token
+    pub fn take(&mut self, rate: f32, burst: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f32);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = Duration::try_from_secs_f32((1.0 - self.tokens) / rate)
+                .map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT));
+            Err(wait)
+        }
+    }
+}
+
+impl Flood {
+    /// Count one rate limit hit
+    pub fn strike(&mut self, config: &RateLimitConfig) -> Penalty {
+        let now = Instant::now();
+        if self
+            .last_strike
+            .is_some_and(|last| now.duration_since(last) >= STRIKE_RESET)
+        {
+            self.strikes = 0;
+        }
+        self.last_strike = Some(now);
+        self.strikes += 1;
+
+        if self.strikes <= config.flood_warnings {
+            return Penalty::Warn {
+                warnings_left: config.flood_warnings - self.strikes,
+            };
+        }
+
+        self.strikes = 0;
+        self.mutes += 1;
+        if config.flood_mutes_before_kick != 0 && self.mutes > config.flood_mutes_before_kick {
+            Penalty::Kick
+        } else {
+            Penalty::Mute(config.flood_mute())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_limited() {
+        let mut bucket = TokenBucket::new(2);
+        assert!(bucket.take(1.0, 2).is_ok());
+        assert!(bucket.take(1.0, 2).is_ok());
+        let wait = bucket.take(1.0, 2).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn tiny_rate_does_not_overflow() {
+        let mut bucket = TokenBucket::new(1);
+        assert!(bucket.take(f32::MIN_POSITIVE, 1).is_ok());
+        assert_eq!(bucket.take(f32::MIN_POSITIVE, 1), Err(MAX_WAIT));
+    }
+}
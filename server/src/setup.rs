@@ -0,0 +1,213 @@
+use crate::config::{Config, ConfigArgs, ConfigError};
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, BufRead, ErrorKind, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+//
+
+/// Default path of the written config file
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+//
+
+/// Ask for the basic server settings and write them
+/// to a config file, returns the path of the file
+///
+/// Values from the chosen config file, if it exists,
+/// and the cli flags are the defaults.
+/// A config file that fails to load is an error,
+/// it would be overwritten with the defaults otherwise.
+pub fn run(args: &ConfigArgs) -> io::Result<PathBuf> {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    println!("Server setup, press enter to use the [default]\n");
+
+    let path = ask(
+        &mut stdin,
+        "Config file",
+        Some(
+            args.config
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CONFIG_PATH.into())
+                .display(),
+        ),
+        |s| Ok(PathBuf::from(s)),
+    )?;
+    let mut config = load(args, &path)?;
+
+    config.server.listen = ask(
+        &mut stdin,
+        "Listen address",
+        Some(config.server.listen),
+        |s| SocketAddr::from_str(s).map_err(|err| err.to_string()),
+    )?;
+
+    config.server.name = ask(
+        &mut stdin,
+        "Server name",
+        Some(&config.server.name),
+        |s| match s {
+            "" => Err("cannot be empty".to_string()),
+            s => Ok(s.to_string()),
+        },
+    )?;
+
+    let admin = ask(
+        &mut stdin,
+        "Admin account id (`tui-chat-app-client --account-id`, 'none' to skip)",
+        config.moderation.admins.first(),
+        |s| match s {
+            "" | "none" => Ok(None),
+            s => Uuid::from_str(s).map(Some).map_err(|err| err.to_string()),
+        },
+    )?;
+    if let Some(admin) = admin {
+        if !config.moderation.admins.contains(&admin) {
+            config.moderation.admins.push(admin);
+        }
+    }
+
+    config.persistence.dir = ask(
+        &mut stdin,
+        "Data directory for the message history and bans ('none' to not save anything)",
+        Some(
+            config
+                .persistence
+                .dir
+                .clone()
+                .unwrap_or_else(|| "data".into())
+                .display(),
+        ),
+        |s| match s {
+            "none" => Ok(None),
+            s => Ok(Some(PathBuf::from(s))),
+        },
+    )?;
+
+    config
+        .validate()
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+    if path.exists() {
+        let overwrite = ask(
+            &mut stdin,
+            &format!(
+                "{} already exists, save these settings to it? (y/n)",
+                path.display()
+            ),
+            Some("n"),
+            |s| match s {
+                "y" | "yes" => Ok(true),
+                "n" | "no" => Ok(false),
+                _ => Err("answer y or n".to_string()),
+            },
+        )?;
+        if !overwrite {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, "Setup cancelled"));
+        }
+    }
+
+    write(&config, &path)?;
+    println!("\nWrote {}\n", path.display());
+
+    Ok(path)
+}
+
+/// Load the config file at `path` with the cli overrides,
+/// or only the cli overrides if there is no file yet
+fn load(args: &ConfigArgs, path: &Path) -> io::Result<Config> {
+    let args = ConfigArgs {
+        config: Some(path.to_path_buf()),
+        ..args.clone()
+    };
+    match args.load() {
+        Ok(config) => Ok(config),
+        // the file is created by the setup
+        Err(ConfigError::Read { source, .. }) if source.kind() == ErrorKind::NotFound => {
+            let args = ConfigArgs {
+                config: None,
+                ..args
+            };
+            args.load()
+                .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))
+        }
+        Err(err) => Err(io::Error::new(ErrorKind::InvalidData, err)),
+    }
+}
+
+fn write(config: &Config, path: &Path) -> io::Result<()> {
+    let toml = toml::to_string_pretty(config).map_err(io::Error::other)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml)
+}
+
+/// Repeat the question until the answer parses,
+/// an empty answer picks the default
+fn ask<T>(
+    stdin: &mut impl BufRead,
+    question: &str,
+    default: Option<impl Display>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> io::Result<T> {
+    let default = default.map(|default| default.to_string());
+    loop {
+        match &default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        let answer = match (line.trim(), &default) {
+            ("", Some(default)) => default.as_str(),
+            (answer, _) => answer,
+        };
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(err) => println!("  {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn args(config: Option<PathBuf>) -> ConfigArgs {
+        ConfigArgs {
+            config,
+            listen: None,
+            name: None,
+            motd: None,
+            data_dir: None,
+        }
+    }
+
+    #[test]
+    fn defaults_come_from_the_chosen_file() {
+        let dir = TempDir::new();
+        let (first, second) = (dir.join("first.toml"), dir.join("second.toml"));
+        fs::write(&first, "[server]\nname = \"first\"\n").unwrap();
+        fs::write(&second, "[server]\nname = \"second\"\n").unwrap();
+
+        let config = load(&args(Some(first)), &second).unwrap();
+        assert_eq!(config.server.name, "second");
+
+        let missing = load(&args(Some(second)), &dir.join("missing.toml")).unwrap();
+        assert_eq!(missing.server.name, args(None).load().unwrap().server.name);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{persistence, state::Shared};
+use std::{collections::HashSet, future::pending, time::Duration};
+use tokio::time::Instant;
+use tracing::{info, warn};
+use tui_chat_app_common::server::DisconnectReason;
+use uuid::Uuid;
+
+//
+
+/// How often to check if every connection has closed
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+//
+
+/// Resolves on SIGINT or SIGTERM
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(_) => warn!("Failed to listen for SIGTERM"),
+        }
+    }
+
+    ctrl_c().await
+}
+
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        warn!("Failed to listen for SIGINT");
+        pending().await
+    }
+}
+
+/// Refuse new connections, disconnect every member, wait for
+/// the connections to close and save the history and read markers
+///
+/// Another signal stops the waiting early
+pub async fn run(shared: &Shared) {
+    shared.start_shutdown();
+
+    let config = shared.config();
+    let restart_in = config.shutdown.restart_eta_secs.map(Duration::from_secs);
+    // a timeout too long to represent never passes
+    let deadline = Instant::now().checked_add(Duration::from_secs(config.shutdown.timeout_secs));
+    info!(?restart_in, "Shutting down");
+
+    let forced = signal();
+    tokio::pin!(forced);
+
+    let mut disconnected = HashSet::<Uuid>::new();
+    loop {
+        // members that were still in the handshake get disconnected on the next round
+        let members: Vec<Uuid> = shared.members.iter().map(|member| *member.key()).collect();
+        for member_id in members {
+            if disconnected.insert(member_id) {
+                shared.kick(member_id, DisconnectReason::Shutdown { restart_in });
+            }
+        }
+
+        if shared.connections.is_empty() {
+            break;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!(
+                members = shared.members.len(),
+                "Shutdown timed out, dropping the remaining connections"
+            );
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = &mut forced => {
+                warn!("Shutdown forced, dropping the remaining connections");
+                break;
+            }
+        }
+    }
+
+    persistence::save_dirty(shared);
+    #[cfg(unix)]
+    crate::admin::remove_socket(shared);
+    info!("Shut down");
+}
@@ -0,0 +1,646 @@
+use crate::{
+    audit::AuditLog,
+    bans::{Ban, BanTarget, Bans, IpRange},
+    config::{Config, ConfigArgs, ConfigError},
+    connections::Connections,
+    message_senders::{MessageSender, MessageSenders},
+    mutes::{Mute, Mutes},
+    persistence::{self, SaveLocks, Saved, SavedMessage},
+    stats::Stats,
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{broadcast::Sender, mpsc::UnboundedSender};
+use tracing::{error, info, warn};
+use tui_chat_app_common::{
+    client::ModerationAction,
+    duration::format_duration,
+    role::Role,
+    server::{
+        AuditAction, AuditEntry, AuditMember, DisconnectReason, HistoryMessage, NoticeKind,
+        ServerChatPacket, ServerPacket,
+    },
+    trace::PacketTrace,
+};
+use uuid::Uuid;
+
+//
+
+/// State shared between all connection handlers
+pub struct Shared {
+    config: RwLock<Arc<Config>>,
+    /// Used to reload the config
+    config_args: ConfigArgs,
+
+    /// Open connections per address
+    pub connections: Connections,
+
+    /// Members in the chat state
+    pub members: DashMap<Uuid, Member>,
+
+    /// Banned addresses and accounts can't connect
+    pub bans: Bans,
+
+    /// Muted accounts and addresses can't send messages
+    pub mutes: Mutes,
+
+    /// Roles given with [`Shared::set_role`], by account id
+    assigned_roles: DashMap<Uuid, Role>,
+
+    pub audit_log: AuditLog,
+
+    save_locks: SaveLocks,
+
+    /// Packets sent to every member
+    pub broadcast: Sender<ServerPacket>,
+
+    /// Senders of the latest messages
+    pub message_senders: MessageSenders,
+
+    /// Slow mode of the chat channel
+    slow_mode: RwLock<Option<Duration>>,
+
+    history: Mutex<VecDeque<HistoryMessage>>,
+    /// History changed since the last save
+    history_dirty: AtomicBool,
+
+    /// The newest read message by account id
+    read_markers: DashMap<Uuid, Uuid>,
+    /// Read markers changed since the last save
+    read_markers_dirty: AtomicBool,
+
+    pub trace: PacketTrace,
+    pub stats: Stats,
+
+    /// New connections are refused once set
+    shutting_down: AtomicBool,
+}
+
+/// A member in the chat state
+pub struct Member {
+    pub addr: SocketAddr,
+
+    /// `None` for clients that didn't log in
+    pub account_id: Option<Uuid>,
+
+    pub connected_at: Instant,
+    control: UnboundedSender<Control>,
+}
+
+/// Sent to a connection handler from outside of it
+#[derive(Debug)]
+pub enum Control {
+    Disconnect(DisconnectReason),
+}
+
+//
+
+impl Member {
+    pub fn new(
+        addr: SocketAddr,
+        account_id: Option<Uuid>,
+        control: UnboundedSender<Control>,
+    ) -> Self {
+        Self {
+            addr,
+            account_id,
+            connected_at: Instant::now(),
+            control,
+        }
+    }
+}
+
+impl Shared {
+    pub fn new(
+        config: Config,
+        config_args: ConfigArgs,
+        broadcast: Sender<ServerPacket>,
+        trace: PacketTrace,
+        saved: Saved,
+    ) -> Self {
+        let Saved {
+            history,
+            bans,
+            mutes,
+            roles,
+            audit_log,
+            read_markers,
+        } = saved;
+
+        let message_senders = MessageSenders::new(history.iter().map(|saved| {
+            let sender = MessageSender {
+                member_id: saved.message.sender_id,
+                account_id: saved.account_id,
+            };
+            (saved.message.message_id, sender)
+        }));
+
+        let slow_mode = config.rate_limit.slow_mode();
+        let history_size = config.limits.history_size;
+        let mut history: VecDeque<_> = history.into_iter().map(|saved| saved.message).collect();
+        if history.len() > history_size {
+            history.drain(..history.len() - history_size);
+        }
+
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            config_args,
+            connections: Connections::default(),
+            members: DashMap::new(),
+            bans: Bans::new(bans),
+            mutes: Mutes::new(mutes),
+            assigned_roles: roles.into_iter().collect(),
+            audit_log: AuditLog::new(audit_log),
+            save_locks: SaveLocks::default(),
+            broadcast,
+            message_senders,
+            slow_mode: RwLock::new(slow_mode),
+            history: Mutex::new(history),
+            history_dirty: AtomicBool::new(false),
+            read_markers: read_markers.into_iter().collect(),
+            read_markers_dirty: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            trace,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Disconnect a member, false if it isn't connected
+    pub fn kick(&self, member_id: Uuid, reason: DisconnectReason) -> bool {
+        let Some(member) = self.members.get(&member_id) else {
+            return false;
+        };
+        member.control.send(Control::Disconnect(reason)).is_ok()
+    }
+
+    /// Ban the address and the account of a member and disconnect it,
+    /// `None` never expires
+    pub fn ban_member(
+        &self,
+        member_id: Uuid,
+        reason: String,
+        expires: Option<DateTime<Utc>>,
+    ) -> bool {
+        let Some((addr, account_id)) = self
+            .members
+            .get(&member_id)
+            .map(|member| (member.addr, member.account_id))
+        else {
+            return false;
+        };
+
+        let targets = [
+            Some(BanTarget::Ip(IpRange::from(addr.ip()))),
+            account_id.map(BanTarget::Account),
+        ];
+        for target in targets.into_iter().flatten() {
+            self.ban(Ban {
+                target,
+                reason: reason.clone(),
+                expires,
+            });
+        }
+        true
+    }
+
+    /// Add a ban, save the ban list and
+    /// disconnect every member it matches
+    pub fn ban(&self, ban: Ban) {
+        info!(target = %ban.target, reason = ban.reason, expires = ?ban.expires, "Banned");
+        self.update_bans(|bans| bans.add(ban));
+        self.enforce_bans();
+    }
+
+    /// Disconnect members that are banned
+    pub fn enforce_bans(&self) {
+        for member in self.members.iter() {
+            if let Some(ban) = self.bans.find(member.addr.ip(), member.account_id) {
+                let _ = member
+                    .control
+                    .send(Control::Disconnect(DisconnectReason::Banned {
+                        reason: ban.reason,
+                    }));
+            }
+        }
+    }
+
+    /// Change the bans and save them, one change at a time
+    fn update_bans(&self, update: impl FnOnce(&Bans)) {
+        let _lock = self.save_locks.bans.lock().unwrap();
+        update(&self.bans);
+
+        let Some(dir) = self.config().persistence.dir.clone() else {
+            return;
+        };
+        if let Err(err) = persistence::save_bans(&dir, &self.bans.list()) {
+            error!(dir = %dir.display(), "Failed to save bans: {err}");
+        }
+    }
+
+    /// Record and apply a moderation action,
+    /// `actor` is `None` for the server itself
+    ///
+    /// Permissions are checked by the caller
+    pub fn moderate(&self, actor: Option<Uuid>, member_id: Uuid, action: ModerationAction) {
+        info!(?actor, %member_id, ?action, "Moderation");
+        self.audit(actor, member_id, AuditAction::Moderation(action.clone()));
+
+        // moderators are announced with `ServerChatPacket::Moderation`
+        if actor.is_none() {
+            let message = action.notice(member_id, "the server");
+            let _ = self.broadcast.send(
+                ServerChatPacket::Notice {
+                    kind: NoticeKind::Moderation,
+                    message,
+                }
+                .into(),
+            );
+        }
+
+        match action {
+            ModerationAction::Kick { reason } => {
+                self.kick(member_id, DisconnectReason::Kicked { reason });
+            }
+            ModerationAction::Mute { duration } => {
+                self.mute(member_id, duration);
+            }
+            ModerationAction::Ban { reason, duration } => {
+                self.ban_member(member_id, reason, Ban::expires_in(duration));
+            }
+            ModerationAction::SetRole { role } => {
+                let account_id = self
+                    .members
+                    .get(&member_id)
+                    .and_then(|member| member.account_id);
+                if let Some(account_id) = account_id {
+                    self.set_role(account_id, role);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Messages from a muted member are rejected, also after
+    /// reconnecting with another account or as a guest, `None` unmutes
+    pub fn mute(&self, member_id: Uuid, duration: Option<Duration>) -> bool {
+        let Some((ip, account_id)) = self
+            .members
+            .get(&member_id)
+            .map(|member| (member.addr.ip(), member.account_id))
+        else {
+            return false;
+        };
+        self.update_mutes(|mutes| match duration {
+            Some(duration) => {
+                for target in Mute::targets(ip, account_id) {
+                    info!(%member_id, %target, ?duration, "Muted");
+                    mutes.add(Mute::new(target, duration));
+                }
+            }
+            None => {
+                info!(%member_id, %ip, ?account_id, "Unmuted");
+                mutes.remove(ip, account_id);
+            }
+        });
+        true
+    }
+
+    /// Time left in the mute of a member
+    pub fn muted(&self, member_id: Uuid) -> Option<Duration> {
+        let (addr, account_id) = self
+            .members
+            .get(&member_id)
+            .map(|member| (member.addr, member.account_id))?;
+        self.mutes.find(addr.ip(), account_id)
+    }
+
+    /// Change the mutes and save them, one change at a time
+    fn update_mutes(&self, update: impl FnOnce(&Mutes)) {
+        let _lock = self.save_locks.mutes.lock().unwrap();
+        update(&self.mutes);
+
+        let Some(dir) = self.config().persistence.dir.clone() else {
+            return;
+        };
+        if let Err(err) = persistence::save_mutes(&dir, &self.mutes.list()) {
+            error!(dir = %dir.display(), "Failed to save mutes: {err}");
+        }
+    }
+
+    /// Guest for members that aren't connected
+    pub fn role(&self, member_id: Uuid) -> Role {
+        let account_id = self
+            .members
+            .get(&member_id)
+            .and_then(|member| member.account_id);
+        self.account_role(&self.config(), account_id)
+    }
+
+    /// Roles of every member
+    pub fn roles(&self) -> HashMap<Uuid, Role> {
+        let config = self.config();
+        self.members
+            .iter()
+            .map(|member| (*member.key(), self.account_role(&config, member.account_id)))
+            .collect()
+    }
+
+    /// Owners from the config can't lose their role,
+    /// other given roles override the config
+    pub fn account_role(&self, config: &Config, account_id: Option<Uuid>) -> Role {
+        match config.role(account_id) {
+            Role::Owner => Role::Owner,
+            role => account_id
+                .and_then(|id| self.assigned_roles.get(&id).map(|role| *role))
+                .unwrap_or(role),
+        }
+    }
+
+    /// Give an account a role, saved in
+    /// [`crate::config::PersistenceConfig::dir`]
+    pub fn set_role(&self, account_id: Uuid, role: Role) {
+        let old_roles = self.roles();
+
+        let lock = self.save_locks.roles.lock().unwrap();
+        self.assigned_roles.insert(account_id, role);
+        info!(%account_id, %role, "Role changed");
+
+        if let Some(dir) = self.config().persistence.dir.clone() {
+            let roles = self
+                .assigned_roles
+                .iter()
+                .map(|entry| (*entry.key(), *entry.value()))
+                .collect();
+            if let Err(err) = persistence::save_roles(&dir, roles) {
+                error!(dir = %dir.display(), "Failed to save roles: {err}");
+            }
+        }
+        drop(lock);
+
+        let roles = self.roles();
+        if roles != old_roles {
+            let _ = self
+                .broadcast
+                .send(ServerChatPacket::MemberRoles { roles }.into());
+        }
+    }
+
+    /// Ids of a connected member for the audit log
+    pub fn audit_member(&self, member_id: Uuid) -> AuditMember {
+        AuditMember {
+            member_id,
+            account_id: self
+                .members
+                .get(&member_id)
+                .and_then(|member| member.account_id),
+        }
+    }
+
+    /// Record a moderation action, `actor` is `None` for the server itself
+    pub fn audit(&self, actor: Option<Uuid>, target: Uuid, action: AuditAction) {
+        let entry = AuditEntry {
+            timestamp: SystemTime::now(),
+            actor: actor.map(|actor| self.audit_member(actor)),
+            target: self.audit_member(target),
+            action,
+        };
+        if let Some(dir) = self.config().persistence.dir.clone() {
+            if let Err(err) = persistence::append_audit_log(&dir, &entry) {
+                error!(dir = %dir.display(), "Failed to write the audit log: {err}");
+            }
+        }
+        self.audit_log.push(entry);
+    }
+
+    /// The current config
+    ///
+    /// Don't hold on to it for long,
+    /// it might get reloaded
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Reload the config file and apply the cli overrides again
+    ///
+    /// Connected members keep their connections,
+    /// the new values are used from now on.
+    pub fn reload_config(&self) -> Result<(), ConfigError> {
+        let config = self.config_args.load()?;
+
+        let old = self.config();
+        if config.server.listen != old.server.listen {
+            warn!("Changing `server.listen` requires a restart");
+        }
+        if config.limits.broadcast_buffer != old.limits.broadcast_buffer {
+            warn!("Changing `limits.broadcast_buffer` requires a restart");
+        }
+        if config.admin.socket != old.admin.socket {
+            warn!("Changing `admin.socket` requires a restart");
+        }
+        let info_changed = (
+            &config.server.name,
+            &config.server.description,
+            &config.server.motd,
+        ) != (&old.server.name, &old.server.description, &old.server.motd);
+
+        // the ban and mute files might have been edited by hand
+        if let Some(dir) = &config.persistence.dir {
+            // a ban saved in between would be lost
+            let bans_lock = self.save_locks.bans.lock().unwrap();
+            match persistence::load_bans(dir) {
+                Ok(bans) => self.bans.replace(bans),
+                Err(err) => warn!(dir = %dir.display(), "Failed to reload bans: {err}"),
+            }
+            drop(bans_lock);
+
+            let mutes_lock = self.save_locks.mutes.lock().unwrap();
+            match persistence::load_mutes(dir) {
+                Ok(mutes) => self.mutes.replace(mutes),
+                Err(err) => warn!(dir = %dir.display(), "Failed to reload mutes: {err}"),
+            }
+            drop(mutes_lock);
+        }
+
+        // keep a slow mode set with `/slowmode` unless the file changed it
+        let slow_mode = (config.rate_limit.slow_mode_secs != old.rate_limit.slow_mode_secs)
+            .then(|| config.rate_limit.slow_mode());
+
+        let old_roles = self.roles();
+        *self.config.write().unwrap() = Arc::new(config);
+        self.enforce_bans();
+
+        if let Some(slow_mode) = slow_mode {
+            self.set_slow_mode(slow_mode);
+        }
+
+        let roles = self.roles();
+        if roles != old_roles {
+            let _ = self
+                .broadcast
+                .send(ServerChatPacket::MemberRoles { roles }.into());
+        }
+
+        if info_changed {
+            let _ = self.broadcast.send(self.server_info().into());
+        }
+        Ok(())
+    }
+
+    /// `None` if slow mode is off
+    pub fn slow_mode(&self) -> Option<Duration> {
+        *self.slow_mode.read().unwrap()
+    }
+
+    /// Change the slow mode of the chat channel and tell the room
+    pub fn set_slow_mode(&self, slow_mode: Option<Duration>) {
+        let slow_mode = slow_mode.filter(|slow_mode| !slow_mode.is_zero());
+        info!(?slow_mode, "Slow mode changed");
+        *self.slow_mode.write().unwrap() = slow_mode;
+
+        let message = match slow_mode {
+            Some(slow_mode) => format!(
+                "Slow mode is on, one message every {}",
+                format_duration(slow_mode)
+            ),
+            None => "Slow mode is off".to_string(),
+        };
+        let _ = self.broadcast.send(
+            ServerChatPacket::Notice {
+                kind: NoticeKind::Moderation,
+                message,
+            }
+            .into(),
+        );
+    }
+
+    pub fn server_info(&self) -> ServerChatPacket {
+        let config = self.config();
+        ServerChatPacket::ServerInfo {
+            name: config.server.name.clone(),
+            description: config.server.description.clone(),
+            motd: config.server.motd.clone(),
+        }
+    }
+
+    pub fn push_history(&self, message: HistoryMessage) {
+        let history_size = self.config().limits.history_size;
+        let mut history = self.history.lock().unwrap();
+        while !history.is_empty() && history.len() >= history_size {
+            history.pop_front();
+        }
+        history.push_back(message);
+        self.history_dirty.store(true, Ordering::Release);
+    }
+
+    /// Sender of a message that is still in the history
+    pub fn history_sender(&self, message_id: Uuid) -> Option<Uuid> {
+        self.history
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|message| message.message_id == message_id)
+            .map(|message| message.sender_id)
+    }
+
+    /// False if the message isn't in the history anymore
+    pub fn edit_history(&self, message_id: Uuid, new_message: String) -> bool {
+        let mut history = self.history.lock().unwrap();
+        let Some(message) = history
+            .iter_mut()
+            .find(|message| message.message_id == message_id)
+        else {
+            return false;
+        };
+        message.message = new_message;
+        self.history_dirty.store(true, Ordering::Release);
+        true
+    }
+
+    /// `None` if the message isn't in the history anymore
+    pub fn remove_history(&self, message_id: Uuid) -> Option<HistoryMessage> {
+        let mut history = self.history.lock().unwrap();
+        let i = history
+            .iter()
+            .position(|message| message.message_id == message_id)?;
+        self.history_dirty.store(true, Ordering::Release);
+        history.remove(i)
+    }
+
+    /// Oldest first
+    pub fn history(&self) -> Vec<HistoryMessage> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// The history with the sender accounts, if it changed since the last call
+    pub fn take_dirty_history(&self) -> Option<Vec<SavedMessage>> {
+        if !self.history_dirty.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        let history = self.history().into_iter().map(|message| SavedMessage {
+            account_id: self
+                .message_senders
+                .get(message.message_id)
+                .and_then(|sender| sender.account_id),
+            message,
+        });
+        Some(history.collect())
+    }
+
+    pub fn read_marker(&self, account_id: Uuid) -> Option<Uuid> {
+        self.read_markers.get(&account_id).map(|marker| *marker)
+    }
+
+    /// Only moves the marker forward, to a message that is still in the history
+    pub fn set_read_marker(&self, account_id: Uuid, message_id: Uuid) -> bool {
+        // also keeps other connections of the account from racing this one
+        let history = self.history.lock().unwrap();
+        let position = |message_id| {
+            history
+                .iter()
+                .position(|message| message.message_id == message_id)
+        };
+
+        let Some(new) = position(message_id) else {
+            return false;
+        };
+        let old = self.read_marker(account_id).and_then(position);
+        if old.is_some_and(|old| old >= new) {
+            return false;
+        }
+
+        self.read_markers.insert(account_id, message_id);
+        self.read_markers_dirty.store(true, Ordering::Release);
+        true
+    }
+
+    /// The read markers, if they changed since the last call
+    pub fn take_dirty_read_markers(&self) -> Option<HashMap<Uuid, Uuid>> {
+        self.read_markers_dirty
+            .swap(false, Ordering::AcqRel)
+            .then(|| {
+                self.read_markers
+                    .iter()
+                    .map(|marker| (*marker.key(), *marker.value()))
+                    .collect()
+            })
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    /// Start refusing new connections,
+    /// `false` if the shutdown had already started
+    pub fn start_shutdown(&self) -> bool {
+        !self.shutting_down.swap(true, Ordering::AcqRel)
+    }
+}
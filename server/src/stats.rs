@@ -0,0 +1,78 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tui_chat_app_common::server::ServerInitFailReason;
+
+//
+
+/// Server wide counters
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub packets_in: AtomicU64,
+    pub packets_out: AtomicU64,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+
+    /// Chat messages broadcast
+    pub messages: AtomicU64,
+
+    /// Times a member lagged behind the broadcast
+    pub lag_events: AtomicU64,
+    /// Broadcast packets skipped by lagging members
+    pub lagged_packets: AtomicU64,
+
+    /// Refused connections and failed handshakes by [`fail_kind`]
+    handshake_failures: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+//
+
+impl Stats {
+    pub fn packet_in(&self, bytes: usize) {
+        self.packets_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn packet_out(&self, bytes: usize) {
+        self.packets_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn lagged(&self, skipped: u64) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.lagged_packets.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn handshake_failed(&self, reason: &ServerInitFailReason) {
+        *self
+            .handshake_failures
+            .lock()
+            .unwrap()
+            .entry(fail_kind(reason))
+            .or_default() += 1;
+    }
+
+    pub fn handshake_failures(&self) -> BTreeMap<&'static str, u64> {
+        self.handshake_failures.lock().unwrap().clone()
+    }
+}
+
+/// Name of the [`ServerInitFailReason`] variant, without its fields
+pub fn fail_kind(reason: &ServerInitFailReason) -> &'static str {
+    match reason {
+        ServerInitFailReason::InvalidState => "invalid_state",
+        ServerInitFailReason::InvalidPacket => "invalid_packet",
+        ServerInitFailReason::CompatibilityError(_) => "compatibility_error",
+        ServerInitFailReason::AlreadyConnected => "already_connected",
+        ServerInitFailReason::Custom(_) => "custom",
+        ServerInitFailReason::ServerFull => "server_full",
+        ServerInitFailReason::TooManyConnections { .. } => "too_many_connections",
+        ServerInitFailReason::Banned { .. } => "banned",
+        ServerInitFailReason::ShuttingDown => "shutting_down",
+        _ => "other",
+    }
+}
@@ -0,0 +1,453 @@
+use crate::state::Shared;
+use ::tui::{Frame, Terminal};
+use chrono::{DateTime, Local};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, Stdout},
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    broadcast::{self, error::TryRecvError},
+    mpsc::UnboundedReceiver,
+};
+use tracing::{error, info};
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+};
+use tui_chat_app_common::{
+    client::ModerationAction,
+    duration::format_duration,
+    role::Role,
+    server::{ServerChatPacket, ServerPacket},
+};
+use uuid::Uuid;
+
+//
+
+/// Run the server management tui until the user quits
+///
+/// Server log lines are shown in the tui while it runs.
+pub async fn run(
+    shared: Arc<Shared>,
+    log_recv: UnboundedReceiver<String>,
+    tick_rate: Duration,
+) -> Result<(), Box<dyn Error>> {
+    // setup
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    let result = App::new(shared, log_recv)
+        .run(&mut terminal, tick_rate)
+        .await;
+
+    // restore
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+//
+
+struct App {
+    shared: Arc<Shared>,
+    should_close: bool,
+
+    /// Member table, sorted by connection time
+    members: Vec<MemberRow>,
+    selected: TableState,
+
+    log: VecDeque<LogLine>,
+    log_recv: UnboundedReceiver<String>,
+    /// The log pane shows the audit log instead
+    show_audit: bool,
+    broadcast: broadcast::Receiver<ServerPacket>,
+
+    rates: Rates,
+}
+
+struct MemberRow {
+    id: Uuid,
+    role: Role,
+    addr: SocketAddr,
+    online: Duration,
+    muted: Option<Duration>,
+}
+
+struct LogLine {
+    timestamp: DateTime<Local>,
+    text: String,
+    color: Color,
+}
+
+/// Packets per second, sampled from [`crate::stats::Stats`]
+#[derive(Default)]
+struct Rates {
+    sampled: Option<(Instant, u64, u64)>,
+    packets_in: f64,
+    packets_out: f64,
+}
+
+//
+
+/// Older log lines are dropped
+const MAX_LOG_LINES: usize = 500;
+
+/// Mute length of the mute action
+const MUTE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Ban length of the ban action, permanent bans go in the ban file
+const BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the packet rates are sampled
+const RATE_INTERVAL: Duration = Duration::SECOND;
+
+//
+
+impl App {
+    fn new(shared: Arc<Shared>, log_recv: UnboundedReceiver<String>) -> Self {
+        let broadcast = shared.broadcast.subscribe();
+        Self {
+            shared,
+            should_close: false,
+
+            members: vec![],
+            selected: TableState::default(),
+
+            log: VecDeque::new(),
+            log_recv,
+            show_audit: false,
+            broadcast,
+
+            rates: Rates::default(),
+        }
+    }
+
+    async fn run(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+        tick_rate: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut last_tick = Instant::now();
+        self.tick();
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+
+            let timeout = tick_rate
+                .checked_sub(last_tick.elapsed())
+                .unwrap_or_else(|| Duration::from_secs(0));
+            if crossterm::event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    match key.code {
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.should_close = true
+                        }
+                        KeyCode::Esc | KeyCode::Char('q') => self.should_close = true,
+                        _ => self.key_event(key),
+                    }
+                }
+            }
+            if last_tick.elapsed() >= tick_rate {
+                self.tick();
+                last_tick = Instant::now();
+            }
+            if self.should_close || self.shared.shutting_down() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame<CrosstermBackend<Stdout>>) {
+        let split = Layout::default()
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Percentage(40),
+                Constraint::Length(1),
+                Constraint::Min(3),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ])
+            .direction(Direction::Vertical)
+            .split(frame.size());
+        frame.render_widget(Block::default().borders(Borders::BOTTOM), split[1]);
+        frame.render_widget(Block::default().borders(Borders::BOTTOM), split[3]);
+        frame.render_widget(Block::default().borders(Borders::BOTTOM), split[5]);
+
+        // title
+        let config = self.shared.config();
+        let stats = &self.shared.stats;
+        let title = Spans::from(vec![
+            Span::styled(
+                config.server.name.as_str(),
+                Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(
+                format!(
+                    " - {} - {} online - in {:.1} pkt/s - out {:.1} pkt/s - {} messages - {} lagged",
+                    config.server.listen,
+                    self.members.len(),
+                    self.rates.packets_in,
+                    self.rates.packets_out,
+                    stats.messages.load(Ordering::Relaxed),
+                    stats.lag_events.load(Ordering::Relaxed),
+                ),
+                Style::default().fg(Color::Gray),
+            ),
+        ]);
+        frame.render_widget(Paragraph::new(title), split[0]);
+
+        self.draw_members(frame, split[2]);
+        if self.show_audit {
+            self.draw_audit(frame, split[4]);
+        } else {
+            self.draw_log(frame, split[4]);
+        }
+
+        // help
+        let help =
+            "Up/Down select - k kick - b ban 1d - m mute 5min - u unmute - a audit log - r reload config - q quit";
+        frame.render_widget(
+            Paragraph::new(Span::styled(help, Style::default().fg(Color::DarkGray))),
+            split[6],
+        );
+    }
+
+    fn draw_members(&mut self, frame: &mut Frame<CrosstermBackend<Stdout>>, rect: Rect) {
+        let header = Row::new(["Member", "Role", "Address", "Online", "Muted"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = self.members.iter().map(|member| {
+            Row::new([
+                Cell::from(member.id.to_string()).style(Style::default().fg(Color::LightCyan)),
+                Cell::from(member.role.to_string()),
+                Cell::from(member.addr.to_string()),
+                Cell::from(format_duration(member.online)),
+                Cell::from(member.muted.map(format_duration).unwrap_or_default())
+                    .style(Style::default().fg(Color::Yellow)),
+            ])
+        });
+
+        let table = Table::new(rows)
+            .header(header)
+            .widths(&[
+                Constraint::Length(36),
+                Constraint::Length(9),
+                Constraint::Min(20),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ])
+            .column_spacing(2)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, rect, &mut self.selected);
+    }
+
+    fn draw_log(&mut self, frame: &mut Frame<CrosstermBackend<Stdout>>, rect: Rect) {
+        // newest at the bottom
+        let skip = self.log.len().saturating_sub(rect.height as usize);
+        let lines: Vec<Spans> = self
+            .log
+            .iter()
+            .skip(skip)
+            .map(|line| {
+                Spans::from(vec![
+                    Span::styled(
+                        line.timestamp.format("%H:%M:%S ").to_string(),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(line.text.as_str(), Style::default().fg(line.color)),
+                ])
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), rect);
+    }
+
+    fn draw_audit(&mut self, frame: &mut Frame<CrosstermBackend<Stdout>>, rect: Rect) {
+        let entries = self.shared.audit_log.recent();
+        // newest at the bottom
+        let skip = entries.len().saturating_sub(rect.height as usize);
+        let lines: Vec<Spans> = entries
+            .iter()
+            .skip(skip)
+            .map(|entry| {
+                let actor = entry
+                    .actor
+                    .map_or("server".to_string(), |actor| actor.member_id.to_string());
+                Spans::from(vec![
+                    Span::styled(
+                        DateTime::<Local>::from(entry.timestamp)
+                            .format("%m-%d %H:%M:%S ")
+                            .to_string(),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(
+                        format!("{actor} -> {}: ", entry.target.member_id),
+                        Style::default().fg(Color::LightCyan),
+                    ),
+                    Span::styled(entry.action.to_string(), Style::default().fg(Color::Yellow)),
+                ])
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), rect);
+    }
+
+    fn tick(&mut self) {
+        while let Ok(line) = self.log_recv.try_recv() {
+            let color = match line.trim_start() {
+                l if l.starts_with("ERROR") => Color::Red,
+                l if l.starts_with("WARN") => Color::Yellow,
+                _ => Color::Gray,
+            };
+            self.push_log(line, color);
+        }
+
+        loop {
+            match self.broadcast.try_recv() {
+                Ok(packet) => self.handle_broadcast(packet),
+                Err(TryRecvError::Lagged(n)) => {
+                    self.push_log(format!("Skipped {n} broadcast packets"), Color::Red)
+                }
+                Err(_) => break,
+            }
+        }
+
+        let config = self.shared.config();
+        let mut members: Vec<MemberRow> = self
+            .shared
+            .members
+            .iter()
+            .map(|member| MemberRow {
+                id: *member.key(),
+                role: self.shared.account_role(&config, member.account_id),
+                addr: member.addr,
+                online: member.connected_at.elapsed(),
+                muted: self.shared.mutes.find(member.addr.ip(), member.account_id),
+            })
+            .collect();
+        members.sort_by_key(|member| std::cmp::Reverse(member.online));
+        self.members = members;
+
+        let selected = match self.members.len() {
+            0 => None,
+            len => Some(self.selected.selected().unwrap_or(0).min(len - 1)),
+        };
+        self.selected.select(selected);
+
+        let stats = &self.shared.stats;
+        let now = (
+            Instant::now(),
+            stats.packets_in.load(Ordering::Relaxed),
+            stats.packets_out.load(Ordering::Relaxed),
+        );
+        match self.rates.sampled {
+            Some((at, packets_in, packets_out)) if at.elapsed() >= RATE_INTERVAL => {
+                let secs = at.elapsed().as_secs_f64();
+                self.rates.packets_in = (now.1 - packets_in) as f64 / secs;
+                self.rates.packets_out = (now.2 - packets_out) as f64 / secs;
+                self.rates.sampled = Some(now);
+            }
+            Some(_) => {}
+            None => self.rates.sampled = Some(now),
+        }
+    }
+
+    fn handle_broadcast(&mut self, packet: ServerPacket) {
+        match packet {
+            ServerPacket::Chat(ServerChatPacket::NewMessage {
+                sender_id, message, ..
+            }) => self.push_log(format!("{sender_id}: {message}"), Color::White),
+            ServerPacket::Chat(ServerChatPacket::NewMember { member_id }) => {
+                self.push_log(format!("{member_id} joined"), Color::Green)
+            }
+            ServerPacket::Chat(ServerChatPacket::RemoveMember { member_id, reason }) => {
+                let reason = reason
+                    .map(|reason| format!(" ({reason})"))
+                    .unwrap_or_default();
+                self.push_log(format!("{member_id} left{reason}"), Color::Green)
+            }
+            ServerPacket::Chat(ServerChatPacket::ServerInfo { .. }) => {
+                self.push_log("Server info sent to everyone".to_string(), Color::Gray)
+            }
+            _ => {}
+        }
+    }
+
+    fn push_log(&mut self, text: String, color: Color) {
+        if self.log.len() >= MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogLine {
+            timestamp: Local::now(),
+            text,
+            color,
+        });
+    }
+
+    fn key_event(&mut self, event: KeyEvent) {
+        let selected = self
+            .selected
+            .selected()
+            .and_then(|idx| self.members.get(idx))
+            .map(|member| member.id);
+
+        match (event.code, selected) {
+            (KeyCode::Up, _) => {
+                let idx = self.selected.selected().unwrap_or(0);
+                self.selected.select(Some(idx.saturating_sub(1)));
+            }
+            (KeyCode::Down, _) => {
+                let idx = self.selected.selected().map_or(0, |idx| idx + 1);
+                let last = self.members.len().saturating_sub(1);
+                self.selected.select(Some(idx.min(last)));
+            }
+            (KeyCode::Char('k'), Some(member_id)) => {
+                let reason = "Kicked by the server admin".to_string();
+                self.shared
+                    .moderate(None, member_id, ModerationAction::Kick { reason });
+            }
+            (KeyCode::Char('b'), Some(member_id)) => {
+                let reason = "Banned by the server admin".to_string();
+                self.shared.moderate(
+                    None,
+                    member_id,
+                    ModerationAction::Ban {
+                        reason,
+                        duration: Some(BAN_DURATION),
+                    },
+                );
+            }
+            (KeyCode::Char('m'), Some(member_id)) => {
+                let duration = Some(MUTE_DURATION);
+                self.shared
+                    .moderate(None, member_id, ModerationAction::Mute { duration });
+            }
+            (KeyCode::Char('u'), Some(member_id)) => {
+                self.shared
+                    .moderate(None, member_id, ModerationAction::Mute { duration: None });
+            }
+            (KeyCode::Char('a'), _) => self.show_audit = !self.show_audit,
+            (KeyCode::Char('r'), _) => match self.shared.reload_config() {
+                Ok(()) => info!("Config reloaded"),
+                Err(err) => error!("Config not reloaded: {err}"),
+            },
+            _ => {}
+        }
+    }
+}
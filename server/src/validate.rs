@@ -0,0 +1,54 @@
+use crate::config::Config;
+use tui_chat_app_common::server::RejectReason;
+
+//
+
+/// Check the content of a message before it is broadcast
+///
+/// Returns the trimmed message
+pub fn message(config: &Config, message: &str) -> Result<String, RejectReason> {
+    let message = message.trim();
+
+    if message.is_empty() {
+        return Err(RejectReason::Empty);
+    }
+
+    let max = config.limits.max_message_length;
+    if message.chars().count() > max {
+        return Err(RejectReason::TooLong { max });
+    }
+
+    if message.chars().any(char::is_control) {
+        return Err(RejectReason::ControlCharacters);
+    }
+
+    let lowercase = message.to_lowercase();
+    if config
+        .moderation
+        .blocked_words
+        .iter()
+        .any(|word| lowercase.contains(&word.to_lowercase()))
+    {
+        return Err(RejectReason::Blocked);
+    }
+
+    Ok(message.to_string())
+}
+
+/// Check a moderation reason, it can be empty
+///
+/// Returns the trimmed reason
+pub fn reason(config: &Config, reason: &str) -> Result<String, RejectReason> {
+    let reason = reason.trim();
+
+    let max = config.limits.max_message_length;
+    if reason.chars().count() > max {
+        return Err(RejectReason::TooLong { max });
+    }
+
+    if reason.chars().any(char::is_control) {
+        return Err(RejectReason::ControlCharacters);
+    }
+
+    Ok(reason.to_string())
+}
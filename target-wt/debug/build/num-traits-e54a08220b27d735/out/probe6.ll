@@ -0,0 +1,28 @@
+; ModuleID = 'probe6.b7be1e88a70f061f-cgu.0'
+source_filename = "probe6.b7be1e88a70f061f-cgu.0"
+target datalayout = "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-i128:128-f80:128-n8:16:32:64-S128"
+target triple = "x86_64-unknown-linux-gnu"
+
+@alloc_7971f3465817cc18ad816e3dbdd7087a = private unnamed_addr constant [7 x i8] c"<anon>\00", align 1
+@alloc_9d40747e106cbf85f7bd532d58745d14 = private unnamed_addr constant <{ ptr, [16 x i8] }> <{ ptr @alloc_7971f3465817cc18ad816e3dbdd7087a, [16 x i8] c"\06\00\00\00\00\00\00\00\01\00\00\00\1F\00\00\00" }>, align 8
+
+; probe6::probe
+; Function Attrs: nonlazybind uwtable
+define void @_RNvCsfM3Aj7wiydv_6probe65probe() unnamed_addr #0 {
+start:
+  ret void
+}
+
+; core::panicking::panic_const::panic_const_div_by_zero
+; Function Attrs: cold noinline noreturn nonlazybind uwtable
+declare void @_RNvNtNtCs8NwYtU1Mohg_4core9panicking11panic_const23panic_const_div_by_zero(ptr align 8) unnamed_addr #1
+
+attributes #0 = { nonlazybind uwtable "probe-stack"="inline-asm" "target-cpu"="x86-64" }
+attributes #1 = { cold noinline noreturn nonlazybind uwtable "probe-stack"="inline-asm" "target-cpu"="x86-64" }
+
+!llvm.module.flags = !{!0, !1}
+!llvm.ident = !{!2}
+
+!0 = !{i32 8, !"PIC Level", i32 2}
+!1 = !{i32 2, !"RtLibUseGOT", i32 1}
+!2 = !{!"rustc version 1.97.0-nightly (e50aa6fba 2026-05-19)"}